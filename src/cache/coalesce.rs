@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+type Waiters<T, E> = Vec<oneshot::Sender<Result<T, Arc<E>>>>;
+
+/// Deduplicates concurrent work: while a future for a key is running, others asking for the same
+/// key wait for its result instead of running their own. Only channels are shared between the
+/// requests, so this works across workers, which all run their own runtime.
+pub struct Coalescer<T, E> {
+    in_flight: Arc<Mutex<HashMap<String, Waiters<T, E>>>>,
+}
+
+impl<T, E> Clone for Coalescer<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<T, E> Default for Coalescer<T, E> {
+    fn default() -> Self {
+        Self {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// Unregisters the work for a key when the future running it completes or is dropped. The
+/// waiters of a dropped future see their channel closed and try again.
+struct Leader<'a, T, E> {
+    coalescer: &'a Coalescer<T, E>,
+    key: &'a str,
+    finished: bool,
+}
+
+impl<T: Clone, E> Leader<'_, T, E> {
+    fn finish(mut self, result: &Result<T, Arc<E>>) {
+        self.finished = true;
+        let waiters = self.coalescer.in_flight.lock().unwrap().remove(self.key);
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+impl<T, E> Drop for Leader<'_, T, E> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.coalescer.in_flight.lock().unwrap().remove(self.key);
+        }
+    }
+}
+
+impl<T: Clone, E> Coalescer<T, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of requests waiting for the work in flight for `key`. Requests that stopped
+    /// waiting, e.g. because their client disconnected, are forgotten.
+    pub fn waiting(&self, key: &str) -> usize {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.get_mut(key).map_or(0, |waiters| {
+            waiters.retain(|waiter| !waiter.is_closed());
+            waiters.len()
+        })
+    }
+
+    /// Runs the future created by `work`, unless one is already running for `key`, in which case
+    /// its result is returned instead. Errors are shared as well, so they are wrapped in an `Arc`.
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> Result<T, Arc<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        loop {
+            let waiting = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get_mut(key) {
+                    Some(waiters) => {
+                        let (sender, receiver) = oneshot::channel();
+                        waiters.push(sender);
+                        Some(receiver)
+                    }
+                    None => {
+                        in_flight.insert(key.to_string(), Vec::new());
+                        None
+                    }
+                }
+            };
+
+            match waiting {
+                Some(receiver) => match receiver.await {
+                    Ok(result) => return result,
+                    // The leader was cancelled, take over.
+                    Err(_) => continue,
+                },
+                None => {
+                    let leader = Leader {
+                        coalescer: self,
+                        key,
+                        finished: false,
+                    };
+                    let result = work().await.map_err(Arc::new);
+                    leader.finish(&result);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_coalescing() {
+        let coalescer = Coalescer::<usize, ()>::new();
+        let runs = AtomicUsize::new(0);
+
+        let work = || async {
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+            Ok(runs.fetch_add(1, Ordering::SeqCst))
+        };
+
+        let (a, b, c) = futures_util::join!(
+            coalescer.run("a", work),
+            coalescer.run("a", work),
+            coalescer.run("b", work)
+        );
+
+        assert_eq!(a, b);
+        assert_eq!(coalescer.waiting("a"), 0);
+        assert_ne!(a, c);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(coalescer.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_waiting() {
+        let coalescer = Coalescer::<(), ()>::new();
+        let (release, released) = oneshot::channel::<()>();
+        let leader = coalescer.run("a", || async {
+            let _ = released.await;
+            Ok(())
+        });
+
+        let waiter = || async {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            coalescer.run("a", || async { Ok(()) }).await
+        };
+        let waiting = || async {
+            tokio::time::delay_for(Duration::from_millis(60)).await;
+            let waiting = coalescer.waiting("a");
+            let _ = release.send(());
+            waiting
+        };
+
+        // A waiter that gave up doesn't count.
+        let (_, _, _, waiting) = futures_util::join!(
+            leader,
+            waiter(),
+            tokio::time::timeout(Duration::from_millis(30), waiter()),
+            waiting()
+        );
+        assert_eq!(waiting, 1);
+    }
+}
@@ -0,0 +1,400 @@
+use super::{lru::Lru, Entry};
+use crate::{fetch::Fetched, settings};
+use actix_web::web::{self, Bytes};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// The first line of every file, so that files of another format are never served.
+const MAGIC: &str = "pxcmprs-cache 1";
+
+/// What the disk cache stores.
+#[derive(Clone, Copy)]
+enum Kind {
+    /// Media as fetched from the source.
+    Original,
+
+    /// Transformed media.
+    Variant,
+}
+
+impl Kind {
+    const ALL: [Kind; 2] = [Kind::Original, Kind::Variant];
+
+    fn directory(self) -> &'static str {
+        match self {
+            Kind::Original => "originals",
+            Kind::Variant => "variants",
+        }
+    }
+}
+
+/// A cached file: a few header lines followed by the body.
+struct Record {
+    key: String,
+
+    /// When the record was written, in seconds since the Unix epoch.
+    inserted: u64,
+
+    content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    cache_control: Option<String>,
+    body: Bytes,
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let field = |value: &Option<String>| value.clone().unwrap_or_default();
+        let header = [
+            MAGIC.to_string(),
+            self.key.clone(),
+            self.inserted.to_string(),
+            field(&self.content_type),
+            field(&self.etag),
+            field(&self.last_modified),
+            field(&self.cache_control),
+        ]
+        .join("\n");
+
+        let mut bytes = Vec::with_capacity(header.len() + 1 + self.body.len());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    fn decode(bytes: Vec<u8>) -> Option<Self> {
+        let bytes = Bytes::from(bytes);
+        let mut lines = Vec::with_capacity(7);
+        let mut offset = 0;
+
+        while lines.len() < 7 {
+            let end = offset + bytes[offset..].iter().position(|&byte| byte == b'\n')?;
+            lines.push(std::str::from_utf8(&bytes[offset..end]).ok()?.to_string());
+            offset = end + 1;
+        }
+
+        if lines[0] != MAGIC {
+            return None;
+        }
+
+        let field = |index: usize| Some(lines[index].clone()).filter(|value| !value.is_empty());
+
+        Some(Self {
+            key: lines[1].clone(),
+            inserted: lines[2].parse().ok()?,
+            content_type: field(3),
+            etag: field(4),
+            last_modified: field(5),
+            cache_control: field(6),
+            body: bytes.slice(offset..),
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// A persistent cache of originals and transformed media in a directory, with a byte budget and
+/// LRU eviction. Files are named by the SHA-256 of their key and written atomically, and the index
+/// is rebuilt from the directory on startup, so the cache survives restarts.
+#[derive(Clone)]
+pub struct DiskCache {
+    path: PathBuf,
+    ttl: Duration,
+
+    /// Paths of the cached files relative to `path`. Recency is persisted as modification time.
+    index: Arc<Mutex<Lru<()>>>,
+
+    /// Used to give every temporary file a unique name.
+    writes: Arc<AtomicU64>,
+}
+
+impl DiskCache {
+    /// Opens the cache directory, creating it if needed, and indexes the files in it. Leftovers of
+    /// interrupted writes are removed, and the least recently used files are evicted if the
+    /// directory is larger than `max_size`.
+    pub fn open(path: &Path, max_size: u64, ttl: Duration) -> io::Result<Self> {
+        let _ = fs::remove_dir_all(path.join("tmp"));
+        fs::create_dir_all(path.join("tmp"))?;
+
+        let mut files = Vec::new();
+
+        for kind in Kind::ALL.iter() {
+            let directory = path.join(kind.directory());
+            fs::create_dir_all(&directory)?;
+
+            for file in fs::read_dir(&directory)? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                if !metadata.is_file() {
+                    continue;
+                }
+
+                let name = format!(
+                    "{}/{}",
+                    kind.directory(),
+                    file.file_name().to_string_lossy()
+                );
+                let used = metadata.modified().unwrap_or(UNIX_EPOCH);
+                files.push((used, name, metadata.len()));
+            }
+        }
+
+        files.sort();
+
+        let mut index = Lru::new(max_size);
+        for (_, name, size) in files {
+            for (evicted, _) in index.insert(name, (), size) {
+                let _ = fs::remove_file(path.join(evicted));
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            ttl,
+            index: Arc::new(Mutex::new(index)),
+            writes: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn from_settings(options: &settings::DiskCache, ttl: u64) -> io::Result<Self> {
+        Self::open(&options.path, options.max_size, Duration::from_secs(ttl))
+    }
+
+    fn name(kind: Kind, key: &str) -> String {
+        format!(
+            "{}/{}",
+            kind.directory(),
+            hex::encode(Sha256::digest(key.as_bytes()))
+        )
+    }
+
+    fn remove(&self, name: &str) {
+        self.index.lock().unwrap().remove(name);
+        let _ = fs::remove_file(self.path.join(name));
+    }
+
+    async fn get(&self, kind: Kind, key: &str) -> Option<Record> {
+        let name = Self::name(kind, key);
+
+        // Files that aren't indexed are either gone or still being written.
+        self.index.lock().unwrap().get(&name)?;
+
+        let path = self.path.join(&name);
+        let record = match tokio::fs::read(&path).await.ok().and_then(Record::decode) {
+            Some(record) => record,
+            None => {
+                self.remove(&name);
+                return None;
+            }
+        };
+
+        if record.key != key || now() > record.inserted.saturating_add(self.ttl.as_secs()) {
+            self.remove(&name);
+            return None;
+        }
+
+        // Keep the recency across restarts.
+        let _ = web::block(move || {
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(SystemTime::now())
+        })
+        .await;
+
+        Some(record)
+    }
+
+    async fn insert(&self, kind: Kind, record: Record) -> io::Result<()> {
+        let name = Self::name(kind, &record.key);
+        let bytes = record.encode();
+        let size = bytes.len() as u64;
+
+        // The file would be evicted as soon as it's indexed.
+        if !self.index.lock().unwrap().fits(size) {
+            return Ok(());
+        }
+
+        // Written to a temporary file first and renamed, so that readers never see a partial file.
+        let temp = self.path.join("tmp").join(format!(
+            "{}.{}",
+            name.replace('/', "-"),
+            self.writes.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let written = async {
+            tokio::fs::write(&temp, bytes).await?;
+            tokio::fs::rename(&temp, self.path.join(&name)).await
+        };
+
+        if let Err(err) = written.await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(err);
+        }
+
+        let evicted = self.index.lock().unwrap().insert(name.clone(), (), size);
+        for (evicted, _) in evicted {
+            // The new file replaced the previous version of itself.
+            if evicted != name {
+                let _ = tokio::fs::remove_file(self.path.join(evicted)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a transformed media.
+    pub async fn get_variant(&self, key: &str) -> Option<Entry> {
+        let record = self.get(Kind::Variant, key).await?;
+
+        Some(Entry {
+            body: record.body,
+            content_type: record.content_type.unwrap_or_default(),
+            etag: record.etag.unwrap_or_default(),
+            last_modified: record.last_modified,
+            cache_control: record.cache_control,
+        })
+    }
+
+    /// Stores a transformed media. Nothing is stored if writing the file fails or the media is
+    /// larger than the whole cache.
+    pub async fn insert_variant(&self, key: String, entry: Entry) -> io::Result<()> {
+        let record = Record {
+            key,
+            inserted: now(),
+            content_type: Some(entry.content_type),
+            etag: Some(entry.etag),
+            last_modified: entry.last_modified,
+            cache_control: entry.cache_control,
+            body: entry.body,
+        };
+
+        self.insert(Kind::Variant, record).await
+    }
+
+    /// Looks up media fetched from `url`.
+    pub async fn get_original(&self, url: &Url) -> Option<Fetched> {
+        let record = self.get(Kind::Original, url.as_str()).await?;
+
+        Some(Fetched {
+            bytes: record.body.to_vec(),
+            content_type: record.content_type,
+            etag: record.etag,
+            last_modified: record.last_modified,
+            cache_control: record.cache_control,
+        })
+    }
+
+    /// Stores media fetched from `url`. Nothing is stored if writing the file fails or the media is
+    /// larger than the whole cache.
+    pub async fn insert_original(&self, url: &Url, fetched: Fetched) -> io::Result<()> {
+        let record = Record {
+            key: url.to_string(),
+            inserted: now(),
+            content_type: fetched.content_type,
+            etag: fetched.etag,
+            last_modified: fetched.last_modified,
+            cache_control: fetched.cache_control,
+            body: fetched.bytes.into(),
+        };
+
+        self.insert(Kind::Original, record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(body: &'static [u8]) -> Entry {
+        Entry {
+            body: Bytes::from_static(body),
+            content_type: "image/png".to_string(),
+            etag: "abc".to_string(),
+            last_modified: None,
+            cache_control: Some("public".to_string()),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_disk_cache() {
+        let path = std::env::temp_dir().join(format!("pxcmprs-disk-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let ttl = Duration::from_secs(60);
+
+        let cache = DiskCache::open(&path, 400, ttl).unwrap();
+        cache
+            .insert_variant("a".to_string(), entry(&[1; 100]))
+            .await
+            .unwrap();
+        cache
+            .insert_variant("b".to_string(), entry(&[2; 100]))
+            .await
+            .unwrap();
+
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        let original = Fetched {
+            bytes: vec![3; 10],
+            etag: Some("\"upstream\"".to_string()),
+            ..Default::default()
+        };
+        cache.insert_original(&url, original).await.unwrap();
+
+        let a = cache.get_variant("a").await.unwrap();
+        assert_eq!(&a.body[..], &[1; 100][..]);
+        assert_eq!(a.cache_control.as_deref(), Some("public"));
+        assert_eq!(a.last_modified, None);
+        let original = cache.get_original(&url).await.unwrap();
+        assert_eq!(original.bytes, vec![3; 10]);
+        assert_eq!(original.etag.as_deref(), Some("\"upstream\""));
+
+        // The index is recovered from the directory, including the recency of the files.
+        std::thread::sleep(Duration::from_millis(10));
+        cache.get_variant("a").await.unwrap();
+        drop(cache);
+        let cache = DiskCache::open(&path, 400, ttl).unwrap();
+        assert!(cache.get_variant("a").await.is_some());
+
+        // `b` is the least recently used file.
+        cache
+            .insert_variant("c".to_string(), entry(&[4; 100]))
+            .await
+            .unwrap();
+        assert!(cache.get_variant("b").await.is_none());
+        assert!(cache.get_variant("c").await.is_some());
+        assert!(!path.join(DiskCache::name(Kind::Variant, "b")).exists());
+
+        // Files larger than the whole cache aren't written.
+        cache
+            .insert_variant("large".to_string(), entry(&[6; 500]))
+            .await
+            .unwrap();
+        assert!(cache.get_variant("large").await.is_none());
+        assert!(!path.join(DiskCache::name(Kind::Variant, "large")).exists());
+        assert!(cache.get_variant("c").await.is_some());
+
+        // Failed writes are reported, and nothing is indexed.
+        fs::remove_dir_all(path.join("tmp")).unwrap();
+        assert!(cache
+            .insert_variant("d".to_string(), entry(&[5; 100]))
+            .await
+            .is_err());
+        assert!(cache.get_variant("d").await.is_none());
+        assert!(cache.get_variant("c").await.is_some());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, HashMap};
+
+struct Slot<V> {
+    value: V,
+    size: u64,
+
+    /// When the entry was last used, as a value of `Lru::clock`.
+    used: u64,
+}
+
+/// Bookkeeping for a least recently used cache with a byte budget. The sizes are given by the
+/// caller, so this works for values kept in memory as well as for an index of files.
+pub(crate) struct Lru<V> {
+    max_size: u64,
+    entries: HashMap<String, Slot<V>>,
+
+    /// Keys ordered from least to most recently used.
+    recency: BTreeMap<u64, String>,
+
+    /// Incremented on every use of an entry.
+    clock: u64,
+
+    /// The total size of all entries, in bytes.
+    size: u64,
+}
+
+impl<V> Lru<V> {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            size: 0,
+        }
+    }
+
+    /// Looks up an entry, marking it as recently used.
+    pub fn get(&mut self, key: &str) -> Option<&V> {
+        self.clock += 1;
+        let slot = self.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut slot.used, self.clock);
+
+        self.recency.remove(&previous);
+        self.recency.insert(self.clock, key.to_string());
+
+        Some(&slot.value)
+    }
+
+    /// Whether an entry of `size` bytes fits into the budget at all.
+    pub fn fits(&self, size: u64) -> bool {
+        size <= self.max_size
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.recency.remove(&slot.used);
+        self.size -= slot.size;
+        Some(slot.value)
+    }
+
+    /// Stores an entry of `size` bytes as the most recently used one, evicting the least recently
+    /// used entries until it fits into the budget. Returns the evicted entries, or the entry itself
+    /// if it is larger than the whole budget.
+    pub fn insert(&mut self, key: String, value: V, size: u64) -> Vec<(String, V)> {
+        if size > self.max_size {
+            return vec![(key, value)];
+        }
+
+        let mut evicted = Vec::new();
+
+        if let Some(previous) = self.remove(&key) {
+            evicted.push((key.clone(), previous));
+        }
+
+        while self.size + size > self.max_size {
+            let oldest = match self.recency.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            if let Some(value) = self.remove(&oldest) {
+                evicted.push((oldest, value));
+            }
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.size += size;
+        self.entries.insert(
+            key,
+            Slot {
+                value,
+                size,
+                used: self.clock,
+            },
+        );
+
+        evicted
+    }
+}
@@ -0,0 +1,101 @@
+use super::{lru::Lru, Entry};
+use crate::settings;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Slot {
+    entry: Entry,
+    inserted: Instant,
+}
+
+/// An in-memory LRU cache of transformed media with a byte budget. Entries expire `ttl` after they
+/// were inserted, however recently they were used.
+#[derive(Clone)]
+pub struct MemoryCache {
+    ttl: Duration,
+    lru: Arc<Mutex<Lru<Slot>>>,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: u64, ttl: Duration) -> Self {
+        Self {
+            ttl,
+            lru: Arc::new(Mutex::new(Lru::new(max_size))),
+        }
+    }
+
+    pub fn from_settings(options: &settings::Cache) -> Self {
+        Self::new(options.max_size, Duration::from_secs(options.ttl))
+    }
+
+    /// Looks up an entry, marking it as recently used. Entries older than the TTL are dropped.
+    pub fn get(&self, key: &str) -> Option<Entry> {
+        let mut lru = self.lru.lock().unwrap();
+
+        let slot = lru.get(key)?;
+        if slot.inserted.elapsed() > self.ttl {
+            lru.remove(key);
+            return None;
+        }
+
+        Some(slot.entry.clone())
+    }
+
+    /// Stores an entry, evicting the least recently used ones until it fits into the budget.
+    /// Entries larger than the whole budget aren't stored.
+    pub fn insert(&self, key: String, entry: Entry) {
+        let size = key.len() as u64 + entry.size();
+        let slot = Slot {
+            entry,
+            inserted: Instant::now(),
+        };
+
+        self.lru.lock().unwrap().insert(key, slot, size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: usize) -> Entry {
+        Entry {
+            body: vec![0; size].into(),
+            content_type: String::new(),
+            etag: String::new(),
+            last_modified: None,
+            cache_control: None,
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = MemoryCache::new(300, Duration::from_secs(60));
+
+        cache.insert("a".to_string(), entry(99));
+        cache.insert("b".to_string(), entry(99));
+        cache.insert("c".to_string(), entry(99));
+        assert!(cache.get("a").is_some());
+
+        // `b` is the least recently used entry now.
+        cache.insert("d".to_string(), entry(99));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("d").is_some());
+
+        // Too large to ever fit.
+        cache.insert("e".to_string(), entry(300));
+        assert!(cache.get("e").is_none());
+        assert!(cache.get("a").is_some());
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = MemoryCache::new(300, Duration::from_secs(0));
+
+        cache.insert("a".to_string(), entry(10));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("a").is_none());
+    }
+}
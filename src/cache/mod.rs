@@ -0,0 +1,53 @@
+//! Caching of transformed media, so that popular variants don't have to be fetched and encoded
+//! again for every request.
+
+pub mod coalesce;
+pub mod disk;
+mod lru;
+pub mod memory;
+
+pub use coalesce::Coalescer;
+pub use disk::DiskCache;
+pub use memory::MemoryCache;
+
+use crate::transform::{animation::FrameSelection, encoding::Encoding};
+use actix_web::web::Bytes;
+use url::Url;
+
+/// A transformed media along with the metadata needed to answer (conditional) requests for it.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub body: Bytes,
+    pub content_type: String,
+
+    /// The strong ETag of the output, without quotes.
+    pub etag: String,
+
+    /// The `Last-Modified` of the source.
+    pub last_modified: Option<String>,
+
+    /// The `Cache-Control` of the source.
+    pub cache_control: Option<String>,
+}
+
+impl Entry {
+    /// The approximate number of bytes the entry takes up.
+    pub fn size(&self) -> u64 {
+        (self.body.len()
+            + self.content_type.len()
+            + self.etag.len()
+            + self.last_modified.as_ref().map_or(0, String::len)
+            + self.cache_control.as_ref().map_or(0, String::len)) as u64
+    }
+}
+
+/// The cache key of a variant: the source along with every parameter that affects the output.
+/// Parameters are normalized first, so that e.g. `.webp` and `?format=webp` share an entry.
+pub fn key(
+    url: &Url,
+    encoding: &Encoding,
+    dimensions: (Option<u32>, Option<u32>),
+    frame: Option<FrameSelection>,
+) -> String {
+    format!("{} {:?} {:?} {:?}", url, encoding, dimensions, frame)
+}
@@ -0,0 +1,147 @@
+//! Detection of clients that went away, so that work done for them can be abandoned. Handlers
+//! aren't dropped when a client disconnects, the server only notices once it writes the response.
+
+use crate::transform::budget::Budget;
+use actix_web::dev::Extensions;
+use std::any::Any;
+use std::time::Duration;
+
+/// How often the connection is checked while work is in progress.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// The connection a request was received on, available in the request extensions.
+///
+/// On Unix this is the raw file descriptor of the socket, which actix owns and closes once the
+/// connection is done. By then the number may belong to another connection, so the socket is only
+/// looked at through `cancel_when_closed`, while the request is still being handled. Elsewhere
+/// disconnects aren't detected.
+#[derive(Debug, Clone, Copy)]
+pub struct Connection {
+    #[cfg(unix)]
+    fd: std::os::unix::io::RawFd,
+}
+
+impl Connection {
+    /// Stores the connection in the extensions of its requests. Meant to be passed to
+    /// `HttpServer::on_connect`.
+    pub fn on_connect(io: &dyn Any, extensions: &mut Extensions) {
+        #[cfg(unix)]
+        {
+            use actix_web::rt::net::TcpStream;
+            use std::os::unix::io::AsRawFd;
+
+            if let Some(stream) = io.downcast_ref::<TcpStream>() {
+                extensions.insert(Connection {
+                    fd: stream.as_raw_fd(),
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (io, extensions);
+    }
+
+    /// Checks whether the client closed or reset the connection.
+    ///
+    /// A socket can't tell a client that is gone from one that only shut down its sending side
+    /// after the request (which actix would still answer), so half-closed connections count as
+    /// closed too. HTTP clients rarely half-close, and those that do lose their transformation.
+    ///
+    /// Must only be called while the request is being handled, see `cancel_when_closed`.
+    #[cfg(unix)]
+    fn is_closed(&self) -> bool {
+        let mut byte = 0u8;
+        // SAFETY: the descriptor is still the socket of the request, as the caller guarantees, and
+        // peeking at a single byte without blocking neither consumes data nor changes its state.
+        let read = unsafe {
+            libc::recv(
+                self.fd,
+                &mut byte as *mut u8 as *mut libc::c_void,
+                1,
+                libc::MSG_PEEK | libc::MSG_DONTWAIT,
+            )
+        };
+
+        match read {
+            0 => true,
+            -1 => !matches!(
+                std::io::Error::last_os_error().kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Watches the connection until `budget` is cancelled, cancelling it if the client goes away
+    /// and `abandon` agrees.
+    ///
+    /// The budget must be cancelled when the request is done or dropped (see
+    /// `Budget::cancel_on_drop`), and this must run on the worker handling the request. actix
+    /// closes the socket in the same step as it drops the requests still in flight on it, and a
+    /// worker runs one task at a time, so the budget is seen cancelled before the socket is gone.
+    pub async fn cancel_when_closed<F: Fn() -> bool>(self, budget: &Budget, abandon: F) {
+        while !budget.is_cancelled() {
+            if self.is_closed() && abandon() {
+                budget.cancel();
+                return;
+            }
+            tokio::time::delay_for(INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    fn wait_until_closed(connection: Connection) -> bool {
+        (0..50).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            connection.is_closed()
+        })
+    }
+
+    #[test]
+    fn test_is_closed() {
+        let (client, server) = connect();
+        let connection = Connection {
+            fd: server.as_raw_fd(),
+        };
+        assert!(!connection.is_closed());
+
+        drop(client);
+        assert!(wait_until_closed(connection));
+    }
+
+    #[test]
+    fn test_half_closed() {
+        let (client, server) = connect();
+        let connection = Connection {
+            fd: server.as_raw_fd(),
+        };
+
+        // Pending request data doesn't count as closed, the end of the stream after it does.
+        (&client).write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!connection.is_closed());
+
+        let mut request = [0; 18];
+        (&server).read_exact(&mut request).unwrap();
+        assert!(wait_until_closed(connection));
+    }
+}
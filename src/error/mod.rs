@@ -7,6 +7,7 @@ use actix_web::{
 };
 use failure::Fail;
 use std::str::Utf8Error;
+use std::sync::Arc;
 
 #[derive(Fail, Debug)]
 pub enum PxcmprsError {
@@ -16,15 +17,32 @@ pub enum PxcmprsError {
     UnicodeError(#[cause] Utf8Error),
     #[fail(display = "invalid url (got: {})", _1)]
     UrlParseError(#[cause] url::ParseError, String),
+    #[fail(display = "missing or invalid signature")]
+    InvalidSignature,
+    #[fail(display = "the url has expired")]
+    Expired,
+    #[fail(display = "invalid expiry time (got: {})", _0)]
+    InvalidExpiry(String),
+    #[fail(display = "unknown origin `{}`", _0)]
+    UnknownOrigin(String),
+    #[fail(display = "invalid path for origin (got: {})", _0)]
+    InvalidOriginPath(String),
     #[fail(display = "{}", _0)]
     FetchError(#[cause] FetchError),
     #[fail(display = "{}", _0)]
     TransformError(#[cause] TransformError),
+    /// The error of a request that this one was coalesced with.
+    #[fail(display = "{}", _0)]
+    Coalesced(Arc<PxcmprsError>),
 }
 
 impl error::ResponseError for PxcmprsError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after() {
+            response.set_header(header::RETRY_AFTER, retry_after);
+        }
+        response
             .set_header(header::CONTENT_TYPE, header::ContentType::plaintext())
             .body(self.to_string())
     }
@@ -34,8 +52,25 @@ impl error::ResponseError for PxcmprsError {
             PxcmprsError::Base64Error(_) => StatusCode::BAD_REQUEST,
             PxcmprsError::UnicodeError(_) => StatusCode::BAD_REQUEST,
             PxcmprsError::UrlParseError(_, _) => StatusCode::BAD_REQUEST,
+            PxcmprsError::InvalidSignature => StatusCode::FORBIDDEN,
+            PxcmprsError::Expired => StatusCode::GONE,
+            PxcmprsError::InvalidExpiry(_) => StatusCode::BAD_REQUEST,
+            PxcmprsError::UnknownOrigin(_) => StatusCode::NOT_FOUND,
+            PxcmprsError::InvalidOriginPath(_) => StatusCode::BAD_REQUEST,
             PxcmprsError::FetchError(err) => err.status_code(),
             PxcmprsError::TransformError(err) => err.status_code(),
+            PxcmprsError::Coalesced(err) => err.status_code(),
+        }
+    }
+}
+
+impl PxcmprsError {
+    /// Seconds after which the client may retry, if the error is temporary.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            PxcmprsError::TransformError(err) => err.retry_after(),
+            PxcmprsError::Coalesced(err) => err.retry_after(),
+            _ => None,
         }
     }
 }
@@ -63,3 +98,9 @@ impl From<TransformError> for PxcmprsError {
         PxcmprsError::TransformError(err)
     }
 }
+
+impl From<Arc<PxcmprsError>> for PxcmprsError {
+    fn from(err: Arc<PxcmprsError>) -> PxcmprsError {
+        PxcmprsError::Coalesced(err)
+    }
+}
@@ -2,12 +2,26 @@ use actix_web::http::StatusCode;
 use failure::Fail;
 use humansize::{file_size_opts, FileSize};
 use std::fmt;
+use std::net::IpAddr;
 
 #[derive(Fail, Debug)]
 pub enum FetchError {
     FetchError(#[cause] reqwest::Error),
+    Timeout,
     IllegalHost(String),
+    DeniedAddress(String, IpAddr),
     MaxSizeExceeded(u64, u64),
+    RedirectRefused(String),
+    InvalidRedirect(Option<String>),
+    TooManyRedirects(usize),
+    UpstreamNotFound(reqwest::StatusCode),
+    UpstreamError(reqwest::StatusCode),
+    UnsupportedContentType(String),
+    UnsupportedScheme(String),
+    IllegalPath(String),
+    FileNotFound(String),
+    IoError(#[cause] std::io::Error),
+    MissingCredentials(String),
     InvalidInput,
 }
 
@@ -22,13 +36,61 @@ impl fmt::Display for FetchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             FetchError::FetchError(ref e) => write!(f, "an upstream fetch error occurred ({})", e),
+            FetchError::Timeout => write!(f, "the upstream fetch timed out"),
             FetchError::IllegalHost(ref host) => write!(f, "illegal host `{}`", host),
+            FetchError::DeniedAddress(ref host, ref addr) => write!(
+                f,
+                "host `{}` resolves to the denied address `{}`",
+                host, addr
+            ),
             FetchError::MaxSizeExceeded(ref limit, ref input) => write!(
                 f,
                 "the input size limit of {} was exceeded, received {}",
                 format_bytecount(*limit),
                 format_bytecount(*input),
             ),
+            FetchError::RedirectRefused(ref location) => {
+                write!(
+                    f,
+                    "refused to follow the upstream redirect to `{}`",
+                    location
+                )
+            }
+            FetchError::InvalidRedirect(None) => {
+                write!(f, "the upstream redirected without a location")
+            }
+            FetchError::InvalidRedirect(Some(ref location)) => {
+                write!(
+                    f,
+                    "the upstream redirected to the invalid location `{}`",
+                    location
+                )
+            }
+            FetchError::TooManyRedirects(ref limit) => {
+                write!(f, "the upstream redirected more than {} times", limit)
+            }
+            FetchError::UpstreamNotFound(ref status) => {
+                write!(
+                    f,
+                    "the source was not found (upstream responded {})",
+                    status
+                )
+            }
+            FetchError::UpstreamError(ref status) => {
+                write!(f, "the upstream responded with an error ({})", status)
+            }
+            FetchError::UnsupportedContentType(ref content_type) => {
+                write!(f, "unsupported source content type `{}`", content_type)
+            }
+            FetchError::UnsupportedScheme(ref scheme) => {
+                write!(f, "unsupported source scheme `{}`", scheme)
+            }
+            FetchError::IllegalPath(ref path) => write!(f, "illegal path `{}`", path),
+            FetchError::FileNotFound(ref path) => write!(f, "file `{}` not found", path),
+            FetchError::IoError(ref e) => write!(f, "unable to read source file ({})", e),
+            FetchError::MissingCredentials(ref var) => {
+                write!(f, "missing credentials (`{}` is not set)", var)
+            }
             FetchError::InvalidInput => write!(f, "unable to fetch source",),
         }
     }
@@ -38,8 +100,21 @@ impl FetchError {
     pub fn status_code(&self) -> StatusCode {
         match self {
             FetchError::FetchError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FetchError::Timeout => StatusCode::GATEWAY_TIMEOUT,
             FetchError::IllegalHost(_) => StatusCode::FORBIDDEN,
+            FetchError::DeniedAddress(_, _) => StatusCode::FORBIDDEN,
             FetchError::MaxSizeExceeded(_, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            FetchError::RedirectRefused(_) => StatusCode::BAD_GATEWAY,
+            FetchError::InvalidRedirect(_) => StatusCode::BAD_GATEWAY,
+            FetchError::TooManyRedirects(_) => StatusCode::BAD_GATEWAY,
+            FetchError::UpstreamNotFound(_) => StatusCode::NOT_FOUND,
+            FetchError::UpstreamError(_) => StatusCode::BAD_GATEWAY,
+            FetchError::UnsupportedContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FetchError::UnsupportedScheme(_) => StatusCode::BAD_REQUEST,
+            FetchError::IllegalPath(_) => StatusCode::FORBIDDEN,
+            FetchError::FileNotFound(_) => StatusCode::NOT_FOUND,
+            FetchError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FetchError::MissingCredentials(_) => StatusCode::INTERNAL_SERVER_ERROR,
             FetchError::InvalidInput => StatusCode::UNPROCESSABLE_ENTITY,
         }
     }
@@ -47,6 +122,10 @@ impl FetchError {
 
 impl From<reqwest::Error> for FetchError {
     fn from(err: reqwest::Error) -> FetchError {
-        FetchError::FetchError(err)
+        if err.is_timeout() {
+            FetchError::Timeout
+        } else {
+            FetchError::FetchError(err)
+        }
     }
 }
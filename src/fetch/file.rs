@@ -0,0 +1,194 @@
+use super::{assert_within_size_limit, error::FetchError, FetchResult, Fetched, Source};
+use crate::settings;
+use actix_web::http::header::HttpDate;
+use async_trait::async_trait;
+use percent_encoding::percent_decode_str;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tokio::{fs, io::AsyncReadExt};
+use url::Url;
+
+/// Maps a `file://<root>/<path>` URL to a path inside the configured root directory. Segments
+/// that could step outside of the root (`..`, encoded slashes, null bytes) are rejected.
+fn resolve_path(url: &Url, options: &settings::Fetch) -> FetchResult<(PathBuf, PathBuf)> {
+    let name = url.host_str().unwrap_or("");
+    let root = options
+        .file_roots
+        .get(name)
+        .ok_or_else(|| FetchError::IllegalHost(name.to_string()))?;
+
+    let mut path = root.clone();
+
+    for segment in url.path_segments().into_iter().flatten() {
+        let segment = percent_decode_str(segment)
+            .decode_utf8()
+            .map_err(|_| FetchError::IllegalPath(url.path().to_string()))?;
+
+        if segment.is_empty() {
+            continue;
+        }
+
+        if segment == "." || segment == ".." || segment.contains(&['/', '\\', '\0'][..]) {
+            return Err(FetchError::IllegalPath(url.path().to_string()));
+        }
+
+        path.push(segment.as_ref());
+    }
+
+    Ok((root.clone(), path))
+}
+
+/// Reads a file from one of the configured `file_roots`, enforcing `max_size`.
+async fn fetch_file(url: &Url, options: &settings::Fetch) -> FetchResult<Fetched> {
+    let (root, path) = resolve_path(url, options)?;
+
+    let not_found = |err: std::io::Error| match err.kind() {
+        ErrorKind::NotFound => FetchError::FileNotFound(url.path().to_string()),
+        _ => FetchError::IoError(err),
+    };
+
+    // Symlinks are resolved before the check, so a link pointing out of the root is rejected too.
+    let root = fs::canonicalize(root).await.map_err(FetchError::IoError)?;
+    let path = fs::canonicalize(path).await.map_err(not_found)?;
+
+    if !path.starts_with(&root) {
+        return Err(FetchError::IllegalPath(url.path().to_string()));
+    }
+
+    let metadata = fs::metadata(&path).await.map_err(not_found)?;
+
+    if !metadata.is_file() {
+        return Err(FetchError::FileNotFound(url.path().to_string()));
+    }
+
+    assert_within_size_limit(metadata.len(), options.max_size)?;
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(metadata.len() as usize);
+
+    // The file could grow between the metadata call and the read, so the read is capped as well.
+    fs::File::open(&path)
+        .await
+        .map_err(not_found)?
+        .take(options.max_size + 1)
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(FetchError::IoError)?;
+
+    assert_within_size_limit(bytes.len() as u64, options.max_size)?;
+
+    let modified = metadata.modified().ok();
+
+    Ok(Fetched {
+        bytes,
+        content_type: None,
+        // Same scheme as nginx: modification time and size in hex.
+        etag: modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| format!("\"{:x}-{:x}\"", modified.as_secs(), metadata.len())),
+        last_modified: modified.map(|modified| HttpDate::from(modified).to_string()),
+        cache_control: None,
+    })
+}
+
+/// Reads `file://<root>/<path>` sources from the directories configured in `file_roots`.
+pub struct FileSource {
+    options: settings::Fetch,
+}
+
+impl FileSource {
+    pub fn new(options: &settings::Fetch) -> Self {
+        Self {
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Source for FileSource {
+    async fn fetch(&self, url: &Url) -> FetchResult<Fetched> {
+        fetch_file(url, &self.options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    fn options(root: PathBuf) -> settings::Fetch {
+        let mut file_roots = HashMap::new();
+        file_roots.insert("originals".to_string(), root);
+
+        settings::Fetch {
+            allowed_hosts: Regex::new(".*").unwrap(),
+            max_size: 16,
+            allowed_content_types: Vec::new(),
+            denied_networks: Vec::new(),
+            redirect_policy: Default::default(),
+            max_redirects: 0,
+            connect_timeout: 1,
+            timeout: 1,
+            pool_idle_timeout: 1,
+            pool_max_idle_per_host: 1,
+            user_agent: String::new(),
+            file_roots,
+            s3: HashMap::new(),
+        }
+    }
+
+    async fn fetch(url: &str, options: &settings::Fetch) -> FetchResult<Vec<u8>> {
+        Ok(fetch_file(&Url::parse(url).unwrap(), options).await?.bytes)
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_file() {
+        let dir = std::env::temp_dir().join(format!("pxcmprs-fetch-file-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("nested/small file"), b"hello").unwrap();
+        std::fs::write(root.join("large"), [0u8; 32]).unwrap();
+        std::fs::write(dir.join("secret"), b"secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret"), root.join("link")).unwrap();
+
+        let options = options(root);
+
+        assert_eq!(
+            fetch("file://originals/nested/small%20file", &options)
+                .await
+                .unwrap(),
+            b"hello"
+        );
+
+        assert!(matches!(
+            fetch("file://originals/large", &options).await,
+            Err(FetchError::MaxSizeExceeded(16, 32))
+        ));
+        assert!(matches!(
+            fetch("file://originals/missing", &options).await,
+            Err(FetchError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            fetch("file://others/secret", &options).await,
+            Err(FetchError::IllegalHost(_))
+        ));
+        // `..` is normalized away by the URL parser, so this one ends up inside the root.
+        assert!(matches!(
+            fetch("file://originals/../secret", &options).await,
+            Err(FetchError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            fetch("file://originals/nested/..%2F..%2Fsecret", &options).await,
+            Err(FetchError::IllegalPath(_))
+        ));
+        #[cfg(unix)]
+        assert!(matches!(
+            fetch("file://originals/link", &options).await,
+            Err(FetchError::IllegalPath(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
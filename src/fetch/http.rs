@@ -0,0 +1,495 @@
+use super::{assert_within_size_limit, error::FetchError, network, FetchResult, Fetched, Source};
+use crate::settings;
+use async_trait::async_trait;
+use mime::Mime;
+use reqwest::{header, Client, Response, StatusCode};
+use std::time::Duration;
+use url::Url;
+
+/// Makes sure that a URL may be fetched: its host must match `allowed_hosts` and none of the
+/// addresses it resolves to may be inside `denied_networks`.
+async fn assert_allowed_destination(url: &Url, options: &settings::Fetch) -> FetchResult<()> {
+    let host = match url.host_str() {
+        Some(host) if options.allowed_hosts.is_match(host) => host,
+        host => return Err(FetchError::IllegalHost(host.unwrap_or("").to_string())),
+    };
+
+    if options.denied_networks.is_empty() {
+        return Ok(());
+    }
+
+    for addr in network::resolve(url).await? {
+        if network::is_denied(addr, &options.denied_networks) {
+            return Err(FetchError::DeniedAddress(host.to_string(), addr));
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure that the address a response came from isn't inside `denied_networks`. The host was
+/// checked by `assert_allowed_destination` before connecting, but the connection resolves it
+/// again, so a host changing its addresses in between (DNS rebinding) is only caught here.
+fn assert_allowed_remote(res: &Response, url: &Url, options: &settings::Fetch) -> FetchResult<()> {
+    if options.denied_networks.is_empty() {
+        return Ok(());
+    }
+
+    let host = url.host_str().unwrap_or("");
+    match res.remote_addr() {
+        Some(addr) if network::is_denied(addr.ip(), &options.denied_networks) => {
+            Err(FetchError::DeniedAddress(host.to_string(), addr.ip()))
+        }
+        Some(_) => Ok(()),
+        // Only responses that didn't come from a direct connection lack an address.
+        None => Err(FetchError::IllegalHost(host.to_string())),
+    }
+}
+
+/// Turns upstream error responses into errors, so that e.g. an HTML error page never reaches the
+/// decoder.
+fn assert_success_status(status: StatusCode) -> FetchResult<()> {
+    match status {
+        StatusCode::NOT_FOUND | StatusCode::GONE => Err(FetchError::UpstreamNotFound(status)),
+        status if status.is_client_error() || status.is_server_error() => {
+            Err(FetchError::UpstreamError(status))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks whether a content type matches any of the allowed ones. Allowed types may use a
+/// wildcard subtype, like `image/*`.
+fn is_allowed_content_type(content_type: &str, allowed: &[String]) -> bool {
+    let content_type = match content_type.parse::<Mime>() {
+        Ok(content_type) => content_type,
+        Err(_) => return false,
+    };
+
+    allowed
+        .iter()
+        .filter_map(|a| a.parse::<Mime>().ok())
+        .any(|a| {
+            a.type_() == content_type.type_()
+                && (a.subtype() == mime::STAR || a.subtype() == content_type.subtype())
+        })
+}
+
+/// Validates the `Content-Type` of a response against `allowed_content_types`, if configured.
+fn assert_allowed_content_type(res: &Response, options: &settings::Fetch) -> FetchResult<()> {
+    if options.allowed_content_types.is_empty() {
+        return Ok(());
+    }
+
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or("");
+
+    if is_allowed_content_type(content_type, &options.allowed_content_types) {
+        Ok(())
+    } else {
+        Err(FetchError::UnsupportedContentType(content_type.to_string()))
+    }
+}
+
+/// Builds the HTTP client used for all fetches. It is meant to be created once (per worker) and
+/// shared, so that connections to sources are pooled and kept alive between requests.
+pub(crate) fn client(options: &settings::Fetch) -> FetchResult<Client> {
+    Ok(Client::builder()
+        // Redirects are followed by `get`, which validates every hop.
+        .redirect(reqwest::redirect::Policy::none())
+        // Behind a proxy the connection goes to the proxy, and addresses can't be validated.
+        .no_proxy()
+        .connect_timeout(Duration::from_secs(options.connect_timeout))
+        .timeout(Duration::from_secs(options.timeout))
+        .pool_idle_timeout(Duration::from_secs(options.pool_idle_timeout))
+        .pool_max_idle_per_host(options.pool_max_idle_per_host)
+        .user_agent(options.user_agent.as_str())
+        .build()?)
+}
+
+/// Sends a GET request, following redirects manually so that every hop is validated with
+/// `assert_allowed_destination` before anything is sent to it.
+async fn get(client: &Client, url: &Url, options: &settings::Fetch) -> FetchResult<Response> {
+    let mut url = url.clone();
+
+    for _ in 0..=options.max_redirects {
+        assert_allowed_destination(&url, options).await?;
+
+        // reqwest can't be told to connect to the addresses checked above, so the request may
+        // still reach a denied address, but neither its response nor its redirect is used.
+        let res = client.get(url.clone()).send().await?;
+        assert_allowed_remote(&res, &url, options)?;
+
+        if !res.status().is_redirection() {
+            return Ok(res);
+        }
+
+        // The body of a redirect isn't the media, so one that can't be followed is an error.
+        let location = match res.headers().get(header::LOCATION) {
+            Some(location) => String::from_utf8_lossy(location.as_bytes()).to_string(),
+            None => return Err(FetchError::InvalidRedirect(None)),
+        };
+        let location = url
+            .join(&location)
+            .map_err(|_| FetchError::InvalidRedirect(Some(location)))?;
+
+        if !options.redirect_policy.allows(&url, &location) {
+            return Err(FetchError::RedirectRefused(location.to_string()));
+        }
+
+        url = location;
+    }
+
+    Err(FetchError::TooManyRedirects(options.max_redirects))
+}
+
+async fn read_body(client: &Client, url: &Url, options: &settings::Fetch) -> FetchResult<Fetched> {
+    read_response(get(client, url, options).await?, options).await
+}
+
+/// Reads a response into `Fetched`, rejecting error statuses, disallowed content types and bodies
+/// larger than `max_size`.
+pub(crate) async fn read_response(
+    mut res: Response,
+    options: &settings::Fetch,
+) -> FetchResult<Fetched> {
+    assert_success_status(res.status())?;
+    assert_allowed_content_type(&res, options)?;
+
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value: &header::HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header(header::CONTENT_TYPE);
+    let etag = header(header::ETAG);
+    let last_modified = header(header::LAST_MODIFIED);
+    let cache_control = header(header::CACHE_CONTROL);
+
+    // The declared length is only a hint (chunked and compressed responses don't have one),
+    // but if it is present and too large there is no point in downloading anything.
+    if let Some(body_size) = res.content_length() {
+        assert_within_size_limit(body_size, options.max_size)?;
+    }
+
+    let mut bytes: Vec<u8> =
+        Vec::with_capacity(res.content_length().unwrap_or(0).min(options.max_size) as usize);
+
+    // Read the body chunk by chunk and bail out as soon as the limit is passed, so a lying
+    // or missing `Content-Length` can't make us buffer more than `max_size`.
+    while let Some(chunk) = res.chunk().await? {
+        assert_within_size_limit((bytes.len() + chunk.len()) as u64, options.max_size)?;
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(Fetched {
+        bytes,
+        content_type,
+        etag,
+        last_modified,
+        cache_control,
+    })
+}
+
+/// Fetches `http` and `https` sources, validating hosts, addresses and redirects according to the
+/// fetch settings.
+pub struct HttpSource {
+    client: Client,
+    options: settings::Fetch,
+}
+
+impl HttpSource {
+    pub fn new(options: &settings::Fetch) -> FetchResult<Self> {
+        Ok(Self {
+            client: client(options)?,
+            options: options.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Source for HttpSource {
+    async fn fetch(&self, url: &Url) -> FetchResult<Fetched> {
+        // The client's own timeout starts over for every redirect, this one covers the whole fetch.
+        tokio::time::timeout(
+            Duration::from_secs(self.options.timeout),
+            read_body(&self.client, url, &self.options),
+        )
+        .await
+        .map_err(|_| FetchError::Timeout)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{
+        redirect::RedirectPolicy,
+        test_server::{self, chunked, redirect, response, stalled, TestServer},
+    };
+
+    #[test]
+    fn test_is_allowed_content_type() {
+        let allowed = vec![
+            "image/*".to_string(),
+            "application/octet-stream".to_string(),
+        ];
+
+        assert!(is_allowed_content_type("image/png", &allowed));
+        assert!(is_allowed_content_type(
+            "image/svg+xml; charset=utf-8",
+            &allowed
+        ));
+        assert!(is_allowed_content_type(
+            "application/octet-stream",
+            &allowed
+        ));
+        assert!(!is_allowed_content_type(
+            "text/html; charset=utf-8",
+            &allowed
+        ));
+        assert!(!is_allowed_content_type("", &allowed));
+    }
+
+    #[actix_rt::test]
+    async fn test_denied_address() {
+        let server = TestServer::start(vec![("/a.png", response("200 OK", &[], "png"))]);
+        let mut options = test_server::options();
+        options.denied_networks = network::default_denied_networks();
+        let client = client(&options).unwrap();
+
+        // A name resolving to a denied address is rejected before connecting.
+        assert!(matches!(
+            get(&client, &server.url("localhost", "/a.png"), &options).await,
+            Err(FetchError::DeniedAddress(host, _)) if host == "localhost"
+        ));
+        assert!(server.requests().is_empty());
+
+        // A connection that ends up at a denied address anyway is caught by its response.
+        let url = server.url("127.0.0.1", "/a.png");
+        let res = client.get(url.clone()).send().await.unwrap();
+        assert!(matches!(
+            assert_allowed_remote(&res, &url, &options),
+            Err(FetchError::DeniedAddress(_, addr)) if addr == server.addr.ip()
+        ));
+
+        options.denied_networks = vec!["10.0.0.0/8".parse().unwrap()];
+        assert!(assert_allowed_remote(&res, &url, &options).is_ok());
+    }
+
+    /// Redirects from `/3` to `/2` to `/1` to `/0`, which responds with `ok`, and from `/other` to
+    /// `/0` on another host (`localhost`) of the same server.
+    fn redirect_chain() -> TestServer {
+        TestServer::start_with(|addr| {
+            vec![
+                ("/0", response("200 OK", &[], "ok")),
+                ("/1", redirect("/0")),
+                ("/2", redirect("/1")),
+                ("/3", redirect("/2")),
+                (
+                    "/other",
+                    redirect(&format!("http://localhost:{}/0", addr.port())),
+                ),
+            ]
+        })
+    }
+
+    async fn fetch(url: &Url, options: &settings::Fetch) -> FetchResult<Vec<u8>> {
+        let client = client(options).unwrap();
+        Ok(read_body(&client, url, options).await?.bytes)
+    }
+
+    #[actix_rt::test]
+    async fn test_redirect_policy() {
+        let server = redirect_chain();
+        let mut options = test_server::options();
+
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/3"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/other"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+
+        options.redirect_policy = RedirectPolicy::FollowSameHost;
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/3"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/other"), &options).await,
+            Err(FetchError::RedirectRefused(location)) if location.starts_with("http://localhost:")
+        ));
+
+        options.redirect_policy = RedirectPolicy::Never;
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/1"), &options).await,
+            Err(FetchError::RedirectRefused(_))
+        ));
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/0"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_max_redirects() {
+        let server = redirect_chain();
+        let mut options = test_server::options();
+        options.max_redirects = 2;
+
+        // Exactly `max_redirects` redirects are followed.
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/2"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/3"), &options).await,
+            Err(FetchError::TooManyRedirects(2))
+        ));
+
+        options.max_redirects = 0;
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/0"), &options)
+                .await
+                .unwrap(),
+            b"ok"
+        );
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/1"), &options).await,
+            Err(FetchError::TooManyRedirects(0))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_redirect() {
+        let server = TestServer::start(vec![
+            ("/missing", response("302 Found", &[], "<html>")),
+            ("/invalid", redirect("http://[::1/")),
+        ]);
+        let options = test_server::options();
+
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/missing"), &options).await,
+            Err(FetchError::InvalidRedirect(None))
+        ));
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/invalid"), &options).await,
+            Err(FetchError::InvalidRedirect(Some(location))) if location == "http://[::1/"
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_redirect_to_denied_address() {
+        let server = TestServer::start(vec![("/", redirect("http://127.0.0.2/"))]);
+        let mut options = test_server::options();
+        // The first hop is allowed, the one it redirects to isn't.
+        options.denied_networks = vec!["127.0.0.2/32".parse().unwrap()];
+
+        assert!(matches!(
+            fetch(&server.url("127.0.0.1", "/"), &options).await,
+            Err(FetchError::DeniedAddress(host, _)) if host == "127.0.0.2"
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_chunked_body() {
+        let large = "a".repeat(600);
+        // Cut off before the last chunk, so that only a reader stopping early sees the limit.
+        let mut unterminated = chunked(&[&large, &large, &large]);
+        unterminated.truncate(unterminated.len() - "0\r\n\r\n".len());
+
+        let server = TestServer::start(vec![
+            ("/small", chunked(&["ab", "cd"])),
+            ("/large", chunked(&[&large, &large])),
+            ("/unterminated", unterminated),
+        ]);
+        let options = test_server::options();
+
+        assert_eq!(
+            fetch(&server.url("127.0.0.1", "/small"), &options)
+                .await
+                .unwrap(),
+            b"abcd"
+        );
+        for path in &["/large", "/unterminated"] {
+            assert!(
+                matches!(
+                    fetch(&server.url("127.0.0.1", path), &options).await,
+                    Err(FetchError::MaxSizeExceeded(1024, size)) if size > 1024
+                ),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_upstream_status() {
+        let server = TestServer::start(vec![
+            ("/gone", response("410 Gone", &[], "")),
+            ("/forbidden", response("403 Forbidden", &[], "<html>")),
+            (
+                "/error",
+                response("500 Internal Server Error", &[], "<html>"),
+            ),
+            ("/unavailable", response("503 Service Unavailable", &[], "")),
+        ]);
+        let source = HttpSource::new(&test_server::options()).unwrap();
+
+        // Missing sources stay missing, any other upstream error is a bad gateway.
+        for (path, status) in &[
+            ("/missing", StatusCode::NOT_FOUND),
+            ("/gone", StatusCode::NOT_FOUND),
+            ("/forbidden", StatusCode::BAD_GATEWAY),
+            ("/error", StatusCode::BAD_GATEWAY),
+            ("/unavailable", StatusCode::BAD_GATEWAY),
+        ] {
+            let err = source
+                .fetch(&server.url("127.0.0.1", path))
+                .await
+                .unwrap_err();
+            assert_eq!(err.status_code().as_u16(), status.as_u16(), "{}", path);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_timeout() {
+        let server = TestServer::start(vec![
+            ("/head", stalled("HTTP/1.1 200 OK\r\n")),
+            (
+                "/body",
+                stalled("HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nabc"),
+            ),
+        ]);
+        let mut options = test_server::options();
+        options.timeout = 1;
+        let source = HttpSource::new(&options).unwrap();
+
+        for path in &["/head", "/body"] {
+            let err = source
+                .fetch(&server.url("127.0.0.1", path))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, FetchError::Timeout), "{}: {}", path, err);
+            assert_eq!(err.status_code().as_u16(), 504);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use super::{error::FetchError, FetchResult};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use url::{Host, Url};
+
+/// Networks that are never fetched from unless the configuration says otherwise. This covers
+/// loopback, private (RFC 1918 and ULA), link-local (including the cloud metadata endpoints),
+/// carrier-grade NAT, multicast and other reserved ranges.
+const DEFAULT_DENIED_NETWORKS: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "64:ff9b::/96",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+/// The networks used when `fetch.denied_networks` isn't configured.
+pub fn default_denied_networks() -> Vec<IpNet> {
+    DEFAULT_DENIED_NETWORKS
+        .iter()
+        .map(|network| network.parse().unwrap())
+        .collect()
+}
+
+/// Checks whether an address is inside any of the denied networks. IPv4-mapped IPv6 addresses
+/// (`::ffff:127.0.0.1`) are checked as the IPv4 address they represent.
+pub fn is_denied(addr: IpAddr, denied: &[IpNet]) -> bool {
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    };
+
+    denied.iter().any(|network| network.contains(&addr))
+}
+
+/// Resolves the host of a URL to the addresses a connection could end up at.
+pub async fn resolve(url: &Url) -> FetchResult<Vec<IpAddr>> {
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    match url.host() {
+        Some(Host::Ipv4(addr)) => Ok(vec![IpAddr::V4(addr)]),
+        Some(Host::Ipv6(addr)) => Ok(vec![IpAddr::V6(addr)]),
+        Some(Host::Domain(domain)) => Ok(tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|_| FetchError::InvalidInput)?
+            .map(|addr| addr.ip())
+            .collect()),
+        None => Err(FetchError::IllegalHost(url.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_denied_networks() {
+        let denied = default_denied_networks();
+
+        for addr in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.31.255.255",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "::1",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_denied(addr.parse().unwrap(), &denied), "{}", addr);
+        }
+
+        for addr in &["93.184.216.34", "2606:2800:220:1::248", "172.32.0.1"] {
+            assert!(!is_denied(addr.parse().unwrap(), &denied), "{}", addr);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use serde::Deserialize;
+use std::fmt;
+use url::Url;
+
+/// Decides which redirects returned by a source are followed.
+#[derive(Debug, Default, Deserialize, Copy, Clone, PartialEq)]
+pub enum RedirectPolicy {
+    /// Follow redirects to any host matching `allowed_hosts`.
+    #[default]
+    #[serde(rename = "follow")]
+    Follow,
+
+    /// Only follow redirects that stay on the same host.
+    #[serde(rename = "follow_same_host")]
+    FollowSameHost,
+
+    /// Never follow redirects.
+    #[serde(rename = "never")]
+    Never,
+}
+
+impl RedirectPolicy {
+    /// Checks whether a redirect from `from` to `to` may be followed.
+    pub fn allows(&self, from: &Url, to: &Url) -> bool {
+        match self {
+            RedirectPolicy::Follow => true,
+            RedirectPolicy::FollowSameHost => from.host() == to.host(),
+            RedirectPolicy::Never => false,
+        }
+    }
+}
+
+impl fmt::Display for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedirectPolicy::Follow => write!(f, "follow"),
+            RedirectPolicy::FollowSameHost => write!(f, "follow same host"),
+            RedirectPolicy::Never => write!(f, "never"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::{error::FetchError, file::FileSource, http::HttpSource, s3::S3Source, FetchResult};
+use crate::settings;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Media fetched from a source, along with the metadata the source knows about it.
+#[derive(Debug, Clone, Default)]
+pub struct Fetched {
+    pub bytes: Vec<u8>,
+
+    /// The MIME type reported by the source, if any.
+    pub content_type: Option<String>,
+
+    /// An opaque identifier of this version of the media, like the HTTP `ETag`.
+    pub etag: Option<String>,
+
+    /// When the media was last modified, as an HTTP date.
+    pub last_modified: Option<String>,
+
+    /// The caching policy of the source, like the HTTP `Cache-Control`.
+    pub cache_control: Option<String>,
+}
+
+/// An origin that media can be fetched from. Implement this to serve media from other places than
+/// the built-in HTTP and filesystem sources, and register it in `Sources` under its own URL scheme.
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Fetches the media identified by `url`. Implementations are expected to respect the
+    /// `max_size` of the fetch settings.
+    async fn fetch(&self, url: &Url) -> FetchResult<Fetched>;
+}
+
+/// The registered sources, looked up by URL scheme.
+#[derive(Clone, Default)]
+pub struct Sources {
+    sources: HashMap<String, Arc<dyn Source>>,
+}
+
+impl Sources {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with the built-in sources: `http`, `https`, `file` and `s3`.
+    pub fn from_settings(options: &settings::Fetch) -> FetchResult<Self> {
+        let http: Arc<dyn Source> = Arc::new(HttpSource::new(options)?);
+
+        Ok(Self::new()
+            .with("http", http.clone())
+            .with("https", http)
+            .with("file", Arc::new(FileSource::new(options)))
+            .with("s3", Arc::new(S3Source::new(options)?)))
+    }
+
+    /// Registers a source for a URL scheme, replacing any source previously registered for it.
+    pub fn with(mut self, scheme: &str, source: Arc<dyn Source>) -> Self {
+        self.sources.insert(scheme.to_lowercase(), source);
+        self
+    }
+
+    /// Fetches `url` from the source registered for its scheme.
+    pub async fn fetch(&self, url: &Url) -> FetchResult<Fetched> {
+        match self.sources.get(url.scheme()) {
+            Some(source) => source.fetch(url).await,
+            None => Err(FetchError::UnsupportedScheme(url.scheme().to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Static;
+
+    #[async_trait]
+    impl Source for Static {
+        async fn fetch(&self, url: &Url) -> FetchResult<Fetched> {
+            Ok(Fetched {
+                bytes: url.path().as_bytes().to_vec(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_custom_source() {
+        let sources = Sources::new().with("static", Arc::new(Static));
+
+        let fetched = sources
+            .fetch(&Url::parse("static://media/a.png").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(fetched.bytes, b"/a.png");
+
+        assert!(matches!(
+            sources
+                .fetch(&Url::parse("https://example.com/").unwrap())
+                .await,
+            Err(FetchError::UnsupportedScheme(_))
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! A minimal HTTP server answering with canned responses, for testing sources against a real
+//! connection.
+
+use crate::settings;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use url::Url;
+
+/// A raw HTTP response with a status line, headers and a body.
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!(
+        "content-length: {}\r\nconnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    response
+}
+
+/// A `200 OK` sending `chunks` with chunked transfer encoding, so without a `Content-Length`.
+pub fn chunked(chunks: &[&str]) -> String {
+    let mut response =
+        "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n".to_string();
+    for chunk in chunks.iter().chain(&[""]) {
+        response.push_str(&format!("{:x}\r\n{}\r\n", chunk.len(), chunk));
+    }
+    response
+}
+
+/// A `302 Found` pointing at `location`.
+pub fn redirect(location: &str) -> String {
+    response("302 Found", &[("location", location)], "")
+}
+
+/// Sends the beginning of a response and then nothing, without closing the connection.
+pub fn stalled(partial: &str) -> String {
+    partial.to_string()
+}
+
+/// Serves a response per path (`404 Not Found` for any other) on `127.0.0.1` until the tests
+/// exit, and records the head of every request it receives. Connections are closed after the
+/// response if it says so (`connection: close`), and kept open otherwise.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn start(routes: Vec<(&str, String)>) -> Self {
+        Self::start_with(|_| routes)
+    }
+
+    /// Like `start`, for routes that need to know the address of the server.
+    pub fn start_with<'a>(routes: impl FnOnce(SocketAddr) -> Vec<(&'a str, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes: HashMap<String, String> = routes(addr)
+            .into_iter()
+            .map(|(path, response)| (path.to_string(), response))
+            .collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            let mut open = Vec::new();
+            for mut stream in listener.incoming().flatten() {
+                let mut head = Vec::new();
+                let mut byte = [0];
+                while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_string();
+
+                let path = head.split(' ').nth(1).unwrap_or("").to_string();
+                recorded.lock().unwrap().push(head);
+
+                let not_found = response("404 Not Found", &[], "");
+                let response = routes.get(&path).unwrap_or(&not_found);
+                let _ = stream.write_all(response.as_bytes());
+                if !response.contains("connection: close") {
+                    open.push(stream);
+                }
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    /// The URL of `path` on this server, addressed by `host`.
+    pub fn url(&self, host: &str, path: &str) -> Url {
+        Url::parse(&format!("http://{}:{}{}", host, self.addr.port(), path)).unwrap()
+    }
+
+    /// The heads of all requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Fetch settings allowing any host and address, to be adjusted by the tests.
+pub fn options() -> settings::Fetch {
+    settings::Fetch {
+        allowed_hosts: Regex::new(".*").unwrap(),
+        max_size: 1024,
+        allowed_content_types: Vec::new(),
+        denied_networks: Vec::new(),
+        redirect_policy: Default::default(),
+        max_redirects: 10,
+        connect_timeout: 5,
+        timeout: 5,
+        pool_idle_timeout: 1,
+        pool_max_idle_per_host: 1,
+        user_agent: String::new(),
+        file_roots: HashMap::new(),
+        s3: HashMap::new(),
+    }
+}
@@ -1,4 +1,10 @@
+#![allow(non_local_definitions)]
+
+pub mod cache;
+pub mod connection;
 pub mod error;
 pub mod fetch;
+pub mod server;
 pub mod settings;
+pub mod signing;
 pub mod transform;
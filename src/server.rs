@@ -0,0 +1,727 @@
+use super::{
+    cache::{self, Coalescer, DiskCache, Entry, MemoryCache},
+    connection::Connection,
+    error,
+    fetch::{FetchResult, Fetched, Sources},
+    settings,
+    settings::{Origins, Settings},
+    signing::{self, Signing},
+    transform::{
+        self,
+        animation::FrameSelection,
+        budget::Budget,
+        encoding::{Encoding, Serializable as SerializableEncoding},
+        error::TransformError,
+        pool::Pool,
+        probe::Probe,
+    },
+};
+use actix_web::{
+    dev::HttpResponseBuilder,
+    http::{
+        header::{self, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch},
+        StatusCode,
+    },
+    rt,
+    web::{self, Bytes},
+    App, HttpRequest, HttpResponse, HttpServer,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::str;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// The response header telling whether the response was served from the cache.
+const CACHE_STATUS: &str = "x-cache";
+
+/// Commands defined in the request path.
+#[derive(Deserialize)]
+struct Command {
+    /// URL of the input, base64url-encoded.
+    source: String,
+
+    encoding: Option<SerializableEncoding>,
+}
+
+/// Commands defined in the request path of a named origin.
+#[derive(Deserialize)]
+struct OriginCommand {
+    /// Name of the origin in the settings. The path of the input, relative to the base URL of the
+    /// origin, is read from the request as it was sent.
+    origin: String,
+}
+
+#[derive(Deserialize)]
+struct Options {
+    /// The output encoding. The extension in the path takes precedence over this.
+    #[serde(alias = "f")]
+    format: Option<SerializableEncoding>,
+
+    #[serde(alias = "q")]
+    quality: Option<u8>,
+
+    /// The speed of AVIF encoding, from 1 (slowest, smallest) to 10.
+    speed: Option<u8>,
+
+    #[serde(alias = "w")]
+    width: Option<u32>,
+
+    #[serde(alias = "h")]
+    height: Option<u32>,
+
+    /// A frame of an animation to extract: an index, `first`, `last` or `middle`.
+    frame: Option<String>,
+}
+
+/// Rejects the request unless it is signed with one of the accepted keys, if any are configured,
+/// and hasn't expired.
+fn verify_signature(req: &HttpRequest) -> Result<(), error::PxcmprsError> {
+    let signing = req.app_data::<Signing>().unwrap();
+
+    if signing.is_enabled() && !signing.verify(req.path(), req.query_string()) {
+        return Err(error::PxcmprsError::InvalidSignature);
+    }
+
+    match signing::expires(req.query_string()) {
+        Some(Err(expires)) => Err(error::PxcmprsError::InvalidExpiry(expires)),
+        Some(Ok(expires)) if signing.is_expired(expires, SystemTime::now()) => {
+            Err(error::PxcmprsError::Expired)
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn pxcmprs(
+    req: HttpRequest,
+    command: web::Path<Command>,
+    options: web::Query<Options>,
+) -> actix_web::Result<HttpResponse, error::PxcmprsError> {
+    verify_signature(&req)?;
+
+    let url_bytes = &base64::decode_config(&command.source, base64::URL_SAFE_NO_PAD)?;
+    let url_str = str::from_utf8(url_bytes)?;
+    let url = Url::parse(url_str)
+        .map_err(|e| error::PxcmprsError::UrlParseError(e, url_str.to_string()))?;
+
+    transform_source(req, url, command.encoding.clone(), options.into_inner()).await
+}
+
+async fn origin(
+    req: HttpRequest,
+    command: web::Path<OriginCommand>,
+    options: web::Query<Options>,
+) -> actix_web::Result<HttpResponse, error::PxcmprsError> {
+    verify_signature(&req)?;
+
+    let origins = req.app_data::<Origins>().unwrap();
+    // The path is taken as it was sent, actix decodes all escapes in it except for a few (like
+    // `%2F`), which makes some paths ambiguous.
+    let path = req.uri().path().splitn(4, '/').nth(3).unwrap_or("");
+    let url = origin_url(origins, &command.origin, path)?;
+
+    transform_source(req, url, None, options.into_inner()).await
+}
+
+/// Characters that are percent-encoded in a segment of an origin path: those that would end the
+/// segment or the path (`/`, `?` and `#`) or change its meaning (`%`), and those that aren't
+/// allowed in URLs at all.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Resolves the percent-encoded `path` of a request against the base URL of an origin.
+fn origin_url(origins: &Origins, origin: &str, path: &str) -> Result<Url, error::PxcmprsError> {
+    let base = origins
+        .get(origin)
+        .ok_or_else(|| error::PxcmprsError::UnknownOrigin(origin.to_string()))?
+        .base_url();
+
+    // Every segment is encoded again, so that e.g. an encoded `?` doesn't start a query.
+    let encoded = path
+        .split('/')
+        .map(|segment| {
+            percent_decode_str(segment)
+                .decode_utf8()
+                .map(|segment| utf8_percent_encode(&segment, PATH_SEGMENT).to_string())
+                .map_err(|_| error::PxcmprsError::InvalidOriginPath(path.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .join("/");
+
+    // Joined as a relative path, so that neither a scheme nor a host can be read from it.
+    let url = base
+        .join(&format!("./{}", encoded))
+        .map_err(|e| error::PxcmprsError::UrlParseError(e, path.to_string()))?;
+
+    // `..` segments can still leave the base path, which is never intended.
+    if !url.as_str().starts_with(base.as_str()) {
+        return Err(error::PxcmprsError::InvalidOriginPath(path.to_string()));
+    }
+
+    Ok(url)
+}
+
+/// Derives the ETag of a transformed media from the identity of its source (the URL and the version
+/// reported by the source) and everything that affects the output.
+fn etag(url: &Url, fetched: &Fetched, encoding: &Encoding, parameters: &str) -> EntityTag {
+    let mut hasher = Sha256::new();
+
+    hasher.update(url.as_str());
+    match (&fetched.etag, &fetched.last_modified) {
+        (None, None) => hasher.update(Sha256::digest(&fetched.bytes)),
+        (etag, last_modified) => {
+            hasher.update(etag.as_deref().unwrap_or(""));
+            hasher.update(last_modified.as_deref().unwrap_or(""));
+        }
+    }
+    hasher.update(format!("{:?}", encoding));
+    hasher.update(parameters);
+
+    EntityTag::strong(hex::encode(&hasher.finalize()[..16]))
+}
+
+/// Checks the conditional request headers. `If-Modified-Since` is only considered when there is no
+/// `If-None-Match`, as required by RFC 7232.
+fn is_not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: Option<&str>) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+
+    match (IfModifiedSince::parse(req), last_modified) {
+        (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified
+            .parse::<HttpDate>()
+            .is_ok_and(|last_modified| SystemTime::from(last_modified) <= SystemTime::from(since)),
+        _ => false,
+    }
+}
+
+/// Builds a response for a transformed media with its caching headers.
+fn response(
+    req: &HttpRequest,
+    status: StatusCode,
+    entry: &Entry,
+    detected: bool,
+    cache_status: &str,
+) -> HttpResponseBuilder {
+    let headers = req.app_data::<settings::Headers>().unwrap();
+
+    let mut response = HttpResponse::build(status);
+    response
+        .set_header(header::VIA, "pxcmprs")
+        .set_header(CACHE_STATUS, cache_status)
+        .set(header::ETag(EntityTag::strong(entry.etag.clone())));
+    if let Some(cache_control) = headers
+        .cache_control
+        .as_ref()
+        .or(entry.cache_control.as_ref())
+    {
+        response.set_header(header::CACHE_CONTROL, cache_control.as_str());
+    }
+    if let Some(last_modified) = &entry.last_modified {
+        response.set_header(header::LAST_MODIFIED, last_modified.as_str());
+    }
+    // The response depends on the `Accept` header when the encoding is picked from it.
+    if detected {
+        response.set_header(header::VARY, "Accept");
+    }
+    response
+}
+
+/// Fetches the original media of a source, from the disk cache if possible. Concurrent fetches of
+/// the same source are coalesced, so one download serves all variants requested in the meantime.
+async fn fetch_original(req: &HttpRequest, url: &Url) -> Result<Fetched, error::PxcmprsError> {
+    let sources = req.app_data::<Sources>().unwrap();
+    let disk = req.app_data::<Option<DiskCache>>().unwrap();
+    let originals = req
+        .app_data::<Coalescer<Fetched, error::PxcmprsError>>()
+        .unwrap();
+
+    let fetched = originals
+        .run(url.as_str(), || async {
+            if let Some(disk) = disk {
+                if let Some(fetched) = disk.get_original(url).await {
+                    return Ok(fetched);
+                }
+            }
+
+            let fetched = sources.fetch(url).await?;
+
+            if let Some(disk) = disk.clone() {
+                let (url, fetched) = (url.clone(), fetched.clone());
+                // The disk cache is only an optimization, failing to fill it costs a later miss.
+                rt::spawn(async move {
+                    if let Err(err) = disk.insert_original(&url, fetched).await {
+                        log::warn!("failed to cache {} on disk: {}", url, err);
+                    }
+                });
+            }
+
+            Ok(fetched)
+        })
+        .await?;
+
+    Ok(fetched)
+}
+
+/// Fetches a source and transforms it according to the request.
+async fn transform_source(
+    req: HttpRequest,
+    url: Url,
+    encoding: Option<SerializableEncoding>,
+    options: Options,
+) -> actix_web::Result<HttpResponse, error::PxcmprsError> {
+    let transform_settings = req.app_data::<settings::Transform>().unwrap();
+    let pool = req.app_data::<Pool>().unwrap();
+    let cache = req.app_data::<MemoryCache>().unwrap();
+    let disk = req.app_data::<Option<DiskCache>>().unwrap();
+
+    let encoding = encoding.or_else(|| options.format.clone());
+    let detected = encoding.is_none();
+    let encoding = encoding
+        .map_or_else(
+            || Ok(Encoding::detect(&req, false)),
+            |serializable| serializable.to_encoding(options.quality, options.speed),
+        )
+        .map_err(TransformError::from)?;
+
+    let new_dimensions = (options.width, options.height);
+    let frame = options
+        .frame
+        .as_deref()
+        .map(str::parse::<FrameSelection>)
+        .transpose()
+        .map_err(TransformError::from)?;
+
+    let key = cache::key(&url, &encoding, new_dimensions, frame);
+
+    let cached = match (cache.get(&key), disk) {
+        (Some(entry), _) => Some(entry),
+        (None, Some(disk)) => disk
+            .get_variant(&key)
+            .await
+            .inspect(|entry| cache.insert(key.clone(), entry.clone())),
+        (None, None) => None,
+    };
+
+    if let Some(entry) = cached {
+        let etag = EntityTag::strong(entry.etag.clone());
+        if is_not_modified(&req, &etag, entry.last_modified.as_deref()) {
+            return Ok(response(&req, StatusCode::NOT_MODIFIED, &entry, detected, "HIT").finish());
+        }
+        return Ok(response(&req, StatusCode::OK, &entry, detected, "HIT")
+            .content_type(entry.content_type.as_str())
+            .body(entry.body));
+    }
+
+    let fetched = fetch_original(&req, &url).await?;
+
+    // The encoding is picked before the source is known, but AVIF would cut an animation down to
+    // its first frame. Animations get the next best encoding the client accepts instead.
+    let encoding = match encoding {
+        Encoding::Avif(..) if detected && frame.is_none() => {
+            let animated = image::guess_format(&fetched.bytes)
+                .ok()
+                .and_then(|format| {
+                    Probe::read(&fetched.bytes, format, &transform_settings.input).ok()
+                })
+                .is_some_and(|probe| probe.frames > 1);
+            Encoding::detect(&req, animated)
+        }
+        encoding => encoding,
+    };
+
+    let etag = etag(
+        &url,
+        &fetched,
+        &encoding,
+        &format!(
+            "{:?} {:?} {:?}",
+            new_dimensions,
+            frame,
+            transform_settings.limits.get(&encoding)
+        ),
+    );
+
+    let mut entry = Entry {
+        body: Bytes::new(),
+        content_type: encoding.clone().mime_type().to_string(),
+        etag: etag.tag().to_string(),
+        last_modified: fetched.last_modified,
+        cache_control: fetched.cache_control,
+    };
+
+    if is_not_modified(&req, &etag, entry.last_modified.as_deref()) {
+        return Ok(response(&req, StatusCode::NOT_MODIFIED, &entry, detected, "MISS").finish());
+    }
+
+    // Concurrent requests for the same variant wait for a single transformation.
+    let bytes = fetched.bytes;
+    let variants = req
+        .app_data::<Coalescer<Entry, error::PxcmprsError>>()
+        .unwrap();
+    let entry = variants
+        .run(&key, || async {
+            // The variant may have been cached since it was looked up.
+            if let Some(entry) = cache.get(&key) {
+                return Ok(entry);
+            }
+
+            let (limits, input_limits) = (transform_settings.limits, transform_settings.input);
+            let encoding = encoding.clone();
+            let mut budget = Budget::new(Duration::from_secs(transform_settings.time_budget));
+            // Stops the transformation if this future is dropped, or the client disconnects while
+            // no one else is waiting for the result.
+            let _cancel = budget.cancel_on_drop();
+            if let Some(connection) = req.extensions().get::<Connection>().copied() {
+                let (budget, variants, key) = (budget.clone(), variants.clone(), key.clone());
+                rt::spawn(async move {
+                    connection
+                        .cancel_when_closed(&budget, || variants.waiting(&key) == 0)
+                        .await
+                });
+            }
+            entry.body = pool
+                .run(move || {
+                    budget.start();
+                    transform::transform_vec(
+                        bytes,
+                        new_dimensions,
+                        frame,
+                        &encoding,
+                        &limits,
+                        &input_limits,
+                        &budget,
+                    )
+                })
+                .await?
+                .into();
+
+            cache.insert(key.clone(), entry.clone());
+            if let Some(disk) = disk.clone() {
+                let (key, entry) = (key.clone(), entry.clone());
+                rt::spawn(async move {
+                    if let Err(err) = disk.insert_variant(key.clone(), entry).await {
+                        log::warn!("failed to cache variant {} on disk: {}", key, err);
+                    }
+                });
+            }
+
+            Ok(entry)
+        })
+        .await?;
+
+    Ok(response(&req, StatusCode::OK, &entry, detected, "MISS")
+        .content_type(entry.content_type.as_str())
+        .body(entry.body))
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(r#"<html>
+        <body style="background-image: url('/aHR0cHM6Ly91bnNwbGFzaC5jb20vcGhvdG9zL2JzR015QUtENWhrL2Rvd25sb2Fk'); background-size: cover; background-position: center; min-height: 100vh; margin: 0;"/>
+    </html>"#)
+}
+
+fn routes(config: &mut web::ServiceConfig) {
+    config
+        .service(web::resource("/").route(web::get().to(index)))
+        .service(web::resource("/o/{origin}/{path:.+}").route(web::get().to(origin)))
+        .service(
+            web::resource(["/{source}.{encoding}", "/{source}"]).route(web::get().to(pxcmprs)),
+        );
+}
+
+/// Runs the server. `sources` is called once to set up the sources media can be fetched from;
+/// embedders can use it to register their own sources next to the built-in ones. The server
+/// doesn't start if it fails, e.g. because credentials of a bucket are missing.
+pub async fn run<F>(settings: Settings, sources: F) -> std::io::Result<()>
+where
+    F: FnOnce(&settings::Fetch) -> FetchResult<Sources>,
+{
+    let addr = settings.server.socket_addr();
+
+    let transform_settings = settings.transform;
+    let origins = settings.origins;
+    let signing = settings.signing;
+    let headers = settings.headers;
+    // Shared by all workers.
+    let cache = MemoryCache::from_settings(&settings.cache);
+    let pool = Pool::from_settings(&settings.transform)?;
+    let sources = sources(&settings.fetch).map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid fetch settings: {}", err),
+        )
+    })?;
+    let originals = Coalescer::<Fetched, error::PxcmprsError>::new();
+    let variants = Coalescer::<Entry, error::PxcmprsError>::new();
+    let disk = match &settings.cache.disk {
+        Some(options) => Some(DiskCache::from_settings(options, settings.cache.ttl)?),
+        None => None,
+    };
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(transform_settings)
+            .app_data(sources.clone())
+            .app_data(origins.clone())
+            .app_data(signing.clone())
+            .app_data(headers.clone())
+            .app_data(cache.clone())
+            .app_data(disk.clone())
+            .app_data(originals.clone())
+            .app_data(variants.clone())
+            .app_data(pool.clone())
+            .configure(routes)
+    })
+    .on_connect(Connection::on_connect)
+    .bind(addr)
+    .inspect(|_| println!("Successful bind to {}", addr))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{error::FetchError, FetchResult, Source};
+    use crate::transform::{
+        animation::libwebp::AnimationDecoder,
+        limit::{DimensionLimits, InputLimits},
+    };
+    use actix_web::dev::ServiceResponse;
+    use actix_web::test::{self, TestRequest};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_is_not_modified() {
+        let etag = EntityTag::strong("abc".to_string());
+        let last_modified = Some("Wed, 21 Oct 2015 07:28:00 GMT");
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!is_not_modified(&req, &etag, last_modified));
+
+        let req =
+            TestRequest::with_header(header::IF_NONE_MATCH, r#"W/"abc", "def""#).to_http_request();
+        assert!(is_not_modified(&req, &etag, last_modified));
+
+        let req = TestRequest::with_header(header::IF_NONE_MATCH, "*").to_http_request();
+        assert!(is_not_modified(&req, &etag, last_modified));
+
+        // A mismatching `If-None-Match` wins over a matching `If-Modified-Since`.
+        let req = TestRequest::with_header(header::IF_NONE_MATCH, r#""def""#)
+            .header(header::IF_MODIFIED_SINCE, "Wed, 21 Oct 2015 07:28:00 GMT")
+            .to_http_request();
+        assert!(!is_not_modified(&req, &etag, last_modified));
+
+        let req =
+            TestRequest::with_header(header::IF_MODIFIED_SINCE, "Thu, 22 Oct 2015 00:00:00 GMT")
+                .to_http_request();
+        assert!(is_not_modified(&req, &etag, last_modified));
+        assert!(!is_not_modified(&req, &etag, None));
+
+        let req =
+            TestRequest::with_header(header::IF_MODIFIED_SINCE, "Tue, 20 Oct 2015 00:00:00 GMT")
+                .to_http_request();
+        assert!(!is_not_modified(&req, &etag, last_modified));
+    }
+
+    /// Records the URLs it is asked for, without finding any of them.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Source for Recorder {
+        async fn fetch(&self, url: &Url) -> FetchResult<Fetched> {
+            self.0.lock().unwrap().push(url.to_string());
+            Err(FetchError::FileNotFound(url.path().to_string()))
+        }
+    }
+
+    /// Serves the same media for every URL.
+    struct Media(Vec<u8>);
+
+    #[async_trait]
+    impl Source for Media {
+        async fn fetch(&self, _: &Url) -> FetchResult<Fetched> {
+            Ok(Fetched {
+                bytes: self.0.clone(),
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Sends `req` to a server with a single origin, `products`, whose media is fetched from
+    /// `source`.
+    async fn call(source: Arc<dyn Source>, req: TestRequest) -> ServiceResponse {
+        let mut origins = Origins::new();
+        origins.insert(
+            "products".to_string(),
+            settings::Origin {
+                base: Url::parse("https://assets.example.com/img/").unwrap(),
+            },
+        );
+        let transform_settings = settings::Transform {
+            limits: DimensionLimits {
+                jpeg: None,
+                webp: None,
+                png: None,
+                gif: None,
+                avif: None,
+                default: (100, 100),
+            },
+            input: InputLimits::default(),
+            threads: 1,
+            queue_size: 1,
+            retry_after: 1,
+            time_budget: 1,
+        };
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(transform_settings)
+                .app_data(Sources::new().with("https", source))
+                .app_data(origins)
+                .app_data(Signing::default())
+                .app_data(settings::Headers::default())
+                .app_data(MemoryCache::from_settings(&settings::Cache::default()))
+                .app_data(None::<DiskCache>)
+                .app_data(Coalescer::<Fetched, error::PxcmprsError>::new())
+                .app_data(Coalescer::<Entry, error::PxcmprsError>::new())
+                .app_data(Pool::from_settings(&transform_settings).unwrap())
+                .configure(routes),
+        )
+        .await;
+
+        test::call_service(&mut app, req.to_request()).await
+    }
+
+    /// Requests `uri` and returns the status and the URLs fetched for it.
+    async fn get(uri: &str) -> (StatusCode, Vec<String>) {
+        let recorder = Recorder::default();
+        let req = TestRequest::get().uri(uri);
+        let res = call(Arc::new(recorder.clone()), req).await;
+        let fetched = recorder.0.lock().unwrap().clone();
+        (res.status(), fetched)
+    }
+
+    #[actix_rt::test]
+    async fn test_origin() {
+        assert_eq!(
+            get("/o/products/shoes/red.jpg?w=10").await,
+            (
+                StatusCode::NOT_FOUND,
+                vec!["https://assets.example.com/img/shoes/red.jpg".to_string()]
+            )
+        );
+
+        // Encoded characters stay part of the path instead of becoming a query, a fragment or
+        // another segment.
+        assert_eq!(
+            get("/o/products/a%3Fb%23c%25d%20e%2Ff+g.jpg").await.1,
+            vec!["https://assets.example.com/img/a%3Fb%23c%25d%20e%2Ff+g.jpg".to_string()]
+        );
+
+        // Paths looking like absolute URLs are paths below the base as well.
+        for (path, url) in &[
+            (
+                "/o/products//evil.example.com/a.jpg",
+                "https://assets.example.com/img//evil.example.com/a.jpg",
+            ),
+            (
+                "/o/products/https://evil.example.com/a.jpg",
+                "https://assets.example.com/img/https://evil.example.com/a.jpg",
+            ),
+            (
+                "/o/products/https:%2F%2Fevil.example.com%2Fa.jpg",
+                "https://assets.example.com/img/https:%2F%2Fevil.example.com%2Fa.jpg",
+            ),
+        ] {
+            assert_eq!(get(path).await.1, vec![url.to_string()], "{}", path);
+        }
+
+        assert_eq!(
+            get("/o/unknown/shoes/red.jpg").await,
+            (StatusCode::NOT_FOUND, Vec::new())
+        );
+
+        for path in &[
+            "/o/products/../secret.jpg",
+            "/o/products/shoes/../../secret.jpg",
+            "/o/products/shoes/%2E%2E/%2E%2E/secret.jpg",
+        ] {
+            assert_eq!(
+                get(path).await,
+                (StatusCode::BAD_REQUEST, Vec::new()),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_detect_animated() {
+        let request = |source: Vec<u8>| {
+            let req = TestRequest::get()
+                .uri("/o/products/media")
+                .header(header::ACCEPT, "image/avif,image/webp");
+            call(Arc::new(Media(source)), req)
+        };
+
+        // Two 2×2 frames, red and blue.
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, 2, 2, &[255, 0, 0, 0, 0, 255]).unwrap();
+            for color in 0..2 {
+                let frame = gif::Frame {
+                    width: 2,
+                    height: 2,
+                    delay: 10,
+                    buffer: vec![color; 4].into(),
+                    ..Default::default()
+                };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        // AVIF can't be animated, so the animation is sent as WebP.
+        let res = request(gif).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/webp"
+        );
+        let body = test::read_body(res).await;
+        let mut decoder = AnimationDecoder::new(&body).unwrap();
+        let mut frames = 0;
+        while decoder.next_frame().unwrap().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 2);
+
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(2, 2)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let res = request(png).await;
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/avif"
+        );
+    }
+}
@@ -1,25 +1,145 @@
+use crate::fetch::{redirect::RedirectPolicy, s3::S3Bucket};
+use crate::signing::Signing;
 use config::{Config, ConfigError, Environment, File};
 use humansize::{file_size_opts, FileSize};
+use ipnet::IpNet;
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fmt;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use url::Url;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Fetch {
     #[serde(with = "serde_regex")]
     pub allowed_hosts: Regex,
     pub max_size: u64,
+
+    /// Networks that sources may not resolve to. Checked for every request, including redirects.
+    #[serde(default = "crate::fetch::network::default_denied_networks")]
+    pub denied_networks: Vec<IpNet>,
+
+    /// Content types a source may respond with, e.g. `image/*`. Anything is accepted if empty.
+    #[serde(default)]
+    pub allowed_content_types: Vec<String>,
+
+    /// Which redirects to follow. Every hop is validated like the original URL.
+    #[serde(default)]
+    pub redirect_policy: RedirectPolicy,
+
+    /// The maximum number of redirects followed for a single source.
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: usize,
+
+    /// Seconds to wait for a connection to a source to be established.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+
+    /// Seconds a whole fetch (connecting, redirects and reading the body) may take.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+
+    /// Seconds an idle keep-alive connection is kept in the pool.
+    #[serde(default = "default_pool_idle_timeout")]
+    pub pool_idle_timeout: u64,
+
+    /// The maximum number of idle connections kept per host.
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+
+    /// The `User-Agent` sent to sources.
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+
+    /// Named directories that sources can be read from with `file://<name>/<path>` URLs.
+    #[serde(default)]
+    pub file_roots: HashMap<String, PathBuf>,
+
+    /// Named S3-compatible buckets that sources can be read from with `s3://<name>/<key>` URLs.
+    #[serde(default)]
+    pub s3: HashMap<String, S3Bucket>,
+}
+
+fn default_max_redirects() -> usize {
+    10
+}
+
+fn default_connect_timeout() -> u64 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_pool_idle_timeout() -> u64 {
+    90
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    16
+}
+
+fn default_user_agent() -> String {
+    format!("pxcmprs/{}", env!("CARGO_PKG_VERSION"))
 }
 
 impl fmt::Display for Fetch {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "- Allowed hosts: {}", self.allowed_hosts)?;
         writeln!(
             f,
-            "- Allowed hosts: {}\n- Maximum download size: {}",
-            self.allowed_hosts,
+            "- Maximum download size: {}",
             self.max_size.file_size(file_size_opts::BINARY).unwrap()
-        )
+        )?;
+        if !self.allowed_content_types.is_empty() {
+            writeln!(
+                f,
+                "- Allowed content types: {}",
+                self.allowed_content_types.join(", ")
+            )?;
+        }
+        writeln!(
+            f,
+            "- Denied networks: {}",
+            self.denied_networks
+                .iter()
+                .map(IpNet::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+        writeln!(
+            f,
+            "- Redirects: {} (max {})",
+            self.redirect_policy, self.max_redirects
+        )?;
+        writeln!(
+            f,
+            "- Timeouts: {}s to connect, {}s in total",
+            self.connect_timeout, self.timeout
+        )?;
+        writeln!(
+            f,
+            "- Connection pool: {} idle connections per host, closed after {}s",
+            self.pool_max_idle_per_host, self.pool_idle_timeout
+        )?;
+        writeln!(f, "- User agent: {}", self.user_agent)?;
+        for (name, root) in &self.file_roots {
+            writeln!(f, "- File root `{}`: {}", name, root.display())?;
+        }
+        for (name, bucket) in &self.s3 {
+            writeln!(
+                f,
+                "- S3 bucket `{}`: {} in {} ({})",
+                name,
+                bucket.bucket,
+                bucket.region,
+                bucket.endpoint.as_deref().unwrap_or("AWS")
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -44,28 +164,199 @@ impl fmt::Display for Server {
 #[derive(Debug, Deserialize, Copy, Clone)]
 pub struct Transform {
     pub limits: crate::transform::limit::DimensionLimits,
+
+    /// Limits on the size of inputs, checked before decoding.
+    #[serde(default)]
+    pub input: crate::transform::limit::InputLimits,
+
+    /// Threads running transformations. Defaults to the number of CPUs.
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+
+    /// Transformations that may wait for a thread. Requests are rejected with `503` when the queue
+    /// is full.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+
+    /// Seconds clients are asked to wait (with `Retry-After`) when the queue is full.
+    #[serde(default = "default_retry_after")]
+    pub retry_after: u64,
+
+    /// Seconds a single transformation may take once it started.
+    #[serde(default = "default_time_budget")]
+    pub time_budget: u64,
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+fn default_queue_size() -> usize {
+    64
+}
+
+fn default_retry_after() -> u64 {
+    1
+}
+
+fn default_time_budget() -> u64 {
+    10
 }
 
 impl fmt::Display for Transform {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "- Limits: {:?}", self.limits)
+        writeln!(f, "- Limits: {:?}", self.limits)?;
+        writeln!(f, "- Input limits: {:?}", self.input)?;
+        writeln!(f, "- Threads: {}", self.threads)?;
+        writeln!(f, "- Queue size: {}", self.queue_size)?;
+        writeln!(f, "- Time budget: {} s", self.time_budget)
     }
 }
 
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Headers {
+    /// The `Cache-Control` sent with transformed media. The one of the source is passed through if
+    /// this isn't set.
+    pub cache_control: Option<String>,
+}
+
+impl fmt::Display for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "- Cache-Control: {}",
+            self.cache_control.as_deref().unwrap_or("passed through")
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Cache {
+    /// Bytes of transformed media kept in memory. Caching is disabled if this is 0.
+    #[serde(default = "default_cache_max_size")]
+    pub max_size: u64,
+
+    /// Seconds a transformed media (or an original on disk) is served from the cache.
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+
+    /// A directory to persist originals and transformed media in, so that the cache survives
+    /// restarts.
+    pub disk: Option<DiskCache>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DiskCache {
+    pub path: PathBuf,
+
+    /// Bytes the cache directory may take up.
+    #[serde(default = "default_disk_cache_max_size")]
+    pub max_size: u64,
+}
+
+fn default_cache_max_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_cache_ttl() -> u64 {
+    3600
+}
+
+fn default_disk_cache_max_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            max_size: default_cache_max_size(),
+            ttl: default_cache_ttl(),
+            disk: None,
+        }
+    }
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "- Max size: {}",
+            self.max_size.file_size(file_size_opts::BINARY).unwrap()
+        )?;
+        writeln!(f, "- TTL: {} s", self.ttl)?;
+        match &self.disk {
+            Some(disk) => writeln!(
+                f,
+                "- Disk: {} (max size: {})",
+                disk.path.display(),
+                disk.max_size.file_size(file_size_opts::BINARY).unwrap()
+            ),
+            None => writeln!(f, "- Disk: disabled"),
+        }
+    }
+}
+
+/// A named origin, so that sources can be requested with a path relative to `base` instead of a
+/// full URL.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Origin {
+    pub base: Url,
+}
+
+impl Origin {
+    /// The base URL, always ending with a slash so that paths are joined below it.
+    pub fn base_url(&self) -> Url {
+        let mut base = self.base.clone();
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        base
+    }
+}
+
+pub type Origins = HashMap<String, Origin>;
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub fetch: Fetch,
     pub server: Server,
     pub transform: Transform,
+
+    #[serde(default)]
+    pub headers: Headers,
+
+    #[serde(default)]
+    pub cache: Cache,
+
+    #[serde(default)]
+    pub origins: Origins,
+
+    #[serde(default)]
+    pub signing: Signing,
 }
 
 impl fmt::Display for Settings {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Fetch settings:\n{}\nServer settings:\n{}\nTransform settings:\n{}",
-            self.fetch, self.server, self.transform
-        )
+            "Fetch settings:\n{}\nServer settings:\n{}\nTransform settings:\n{}\nResponse headers:\n{}\nCache settings:\n{}",
+            self.fetch, self.server, self.transform, self.headers, self.cache
+        )?;
+        writeln!(
+            f,
+            "\nSigned URLs: {}",
+            match self.signing.keys.len() {
+                0 => "not required".to_string(),
+                keys => format!("required ({} accepted keys)", keys),
+            }
+        )?;
+        if !self.origins.is_empty() {
+            writeln!(f, "\nOrigins:")?;
+            for (name, origin) in &self.origins {
+                writeln!(f, "- {}: {}", name, origin.base)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,218 @@
+//! Signed URLs. When signing keys are configured, every request must carry an HMAC-SHA256
+//! signature over its path and query parameters, so that only URLs handed out by someone holding a
+//! key are served.
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::form_urlencoded;
+
+/// The query parameter holding the signature.
+pub const SIGNATURE_PARAMETER: &str = "s";
+
+/// The query parameter holding the expiry time of a URL, in seconds since the Unix epoch.
+pub const EXPIRES_PARAMETER: &str = "expires";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Signing {
+    /// Accepted signing keys. New URLs should be signed with the first one, the rest are kept
+    /// around while rotating keys. Signatures aren't required if this is empty.
+    #[serde(default)]
+    pub keys: Vec<String>,
+
+    /// Seconds a URL is still accepted after it expired, to allow for clock skew between the
+    /// signer and the server.
+    #[serde(default = "default_expiry_leeway")]
+    pub expiry_leeway: u64,
+}
+
+fn default_expiry_leeway() -> u64 {
+    30
+}
+
+impl Default for Signing {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            expiry_leeway: default_expiry_leeway(),
+        }
+    }
+}
+
+impl Signing {
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Checks the signature in the query string against all accepted keys.
+    pub fn verify(&self, path: &str, query: &str) -> bool {
+        let signature = form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == SIGNATURE_PARAMETER)
+            .and_then(|(_, signature)| {
+                base64::decode_config(signature.as_bytes(), base64::URL_SAFE_NO_PAD).ok()
+            });
+
+        let signature = match signature {
+            Some(signature) => signature,
+            None => return false,
+        };
+
+        let message = canonical_message(path, query);
+
+        self.keys.iter().any(|key| {
+            let mut mac = hmac(key.as_bytes());
+            mac.update(message.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        })
+    }
+
+    /// Checks whether a URL expiring at `expires` (seconds since the Unix epoch) has expired.
+    pub fn is_expired(&self, expires: u64, now: SystemTime) -> bool {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        now > expires.saturating_add(self.expiry_leeway)
+    }
+}
+
+/// Reads the expiry time from a query string. Returns the raw value as the error if it isn't a
+/// valid timestamp.
+pub fn expires(query: &str) -> Option<Result<u64, String>> {
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name == EXPIRES_PARAMETER)
+        .map(|(_, expires)| expires.parse().map_err(|_| expires.into_owned()))
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size")
+}
+
+/// The signed message: the path followed by the query parameters (except the signature itself)
+/// sorted by name, so that reordering parameters doesn't break a signature but changing any of
+/// them does.
+fn canonical_message(path: &str, query: &str) -> String {
+    let mut parameters: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+        .filter(|(name, _)| name != SIGNATURE_PARAMETER)
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    parameters.sort();
+
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(parameters)
+        .finish();
+
+    format!("{}?{}", path, query)
+}
+
+/// Computes the signature of a path and query string.
+pub fn signature(key: &[u8], path: &str, query: &str) -> String {
+    let mut mac = hmac(key);
+    mac.update(canonical_message(path, query).as_bytes());
+    base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Signs a URL path with its query string, like `/aHR0cHM6Ly9leGFtcGxlLmNvbS9hLmpwZw.webp?w=400`,
+/// by appending the signature parameter.
+pub fn sign_url(key: &[u8], path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.find('?') {
+        Some(index) => (&path_and_query[..index], &path_and_query[index + 1..]),
+        None => (path_and_query, ""),
+    };
+
+    let signature = signature(key, path, query);
+
+    if query.is_empty() {
+        format!("{}?{}={}", path, SIGNATURE_PARAMETER, signature)
+    } else {
+        format!("{}?{}&{}={}", path, query, SIGNATURE_PARAMETER, signature)
+    }
+}
+
+/// Signs a URL like `sign_url`, adding an expiry time that is covered by the signature.
+pub fn sign_url_expiring(key: &[u8], path_and_query: &str, expires: SystemTime) -> String {
+    let expires = expires
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let separator = if path_and_query.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    sign_url(
+        key,
+        &format!(
+            "{}{}{}={}",
+            path_and_query, separator, EXPIRES_PARAMETER, expires
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(url: &str) -> (&str, &str) {
+        let index = url.find('?').unwrap();
+        (&url[..index], &url[index + 1..])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signing = Signing {
+            keys: vec!["new".to_string(), "old".to_string()],
+            ..Default::default()
+        };
+
+        let signed = sign_url(b"new", "/o/products/red.jpg?w=400&q=80");
+        let (path, query) = split(&signed);
+        assert!(signing.verify(path, query));
+
+        // Parameter order doesn't matter.
+        let (_, signature) = query.split_at(query.find("s=").unwrap());
+        assert!(signing.verify(path, &format!("q=80&{}&w=400", signature)));
+
+        // Older keys are still accepted.
+        let signed = sign_url(b"old", "/o/products/red.jpg");
+        let (path, query) = split(&signed);
+        assert!(signing.verify(path, query));
+
+        // Tampering with anything invalidates the signature.
+        let signed = sign_url(b"new", "/o/products/red.jpg?w=400");
+        let (path, query) = split(&signed);
+        assert!(!signing.verify("/o/products/blue.jpg", query));
+        assert!(!signing.verify(path, &query.replace("w=400", "w=4000")));
+        assert!(!signing.verify(path, &format!("{}&h=10", query)));
+        assert!(!signing.verify(path, "w=400"));
+
+        let signed = sign_url(b"unknown", "/o/products/red.jpg?w=400");
+        let (path, query) = split(&signed);
+        assert!(!signing.verify(path, query));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let signing = Signing {
+            keys: vec!["key".to_string()],
+            expiry_leeway: 30,
+        };
+        let at = |secs| UNIX_EPOCH + std::time::Duration::from_secs(secs);
+
+        let signed = sign_url_expiring(b"key", "/o/products/red.jpg?w=400", at(1000));
+        let (path, query) = split(&signed);
+        assert!(signing.verify(path, query));
+        assert_eq!(expires(query), Some(Ok(1000)));
+
+        // The expiry is signed as well.
+        assert!(!signing.verify(path, &query.replace("expires=1000", "expires=2000")));
+
+        assert!(!signing.is_expired(1000, at(999)));
+        assert!(!signing.is_expired(1000, at(1030)));
+        assert!(signing.is_expired(1000, at(1031)));
+
+        assert_eq!(expires("w=400"), None);
+        assert_eq!(expires("expires=soon"), Some(Err("soon".to_string())));
+    }
+}
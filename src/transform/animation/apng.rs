@@ -0,0 +1,53 @@
+use crate::transform::{error::DecodeError, probe, TransformResult};
+use image::{codecs::png::PngDecoder, AnimationDecoder, ImageDecoder, RgbaImage};
+use std::io::Cursor;
+
+/// Decodes the frames of an APNG, composited by the `image` crate.
+pub struct Frames<'a> {
+    frames: image::Frames<'a>,
+    dimensions: (u32, u32),
+    plays: u32,
+}
+
+impl<'a> Frames<'a> {
+    pub fn new(bytes: &'a [u8]) -> TransformResult<Self> {
+        let decoder = PngDecoder::new(Cursor::new(bytes)).map_err(DecodeError::from)?;
+        let dimensions = decoder.dimensions();
+
+        // The animation control chunk holds the number of frames and plays.
+        let plays = probe::png_chunks(bytes)
+            .find(|(kind, _)| kind == b"acTL")
+            .and_then(|(_, data)| data.get(4..8))
+            .map_or(1, |plays| {
+                u32::from_be_bytes([plays[0], plays[1], plays[2], plays[3]])
+            });
+
+        Ok(Self {
+            frames: decoder.apng().into_frames(),
+            dimensions,
+            plays,
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    /// How many times the animation is played, or 0 if it's played forever.
+    pub fn plays(&self) -> u32 {
+        self.plays
+    }
+
+    /// Decodes the next frame and how long it's shown, in milliseconds.
+    pub fn next_frame(&mut self) -> TransformResult<Option<(RgbaImage, u32)>> {
+        let frame = match self.frames.next() {
+            Some(frame) => frame.map_err(DecodeError::from)?,
+            None => return Ok(None),
+        };
+
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay = (numerator + denominator / 2) / denominator.max(1);
+
+        Ok(Some((frame.into_buffer(), delay)))
+    }
+}
@@ -0,0 +1,142 @@
+use gif::DisposalMethod;
+use image::{Rgba, RgbaImage};
+
+/// What has to happen to the canvas before the next frame is drawn.
+struct Disposal {
+    method: DisposalMethod,
+
+    /// The area of the frame: left, top, width and height.
+    area: (u32, u32, u32, u32),
+
+    /// The canvas before the frame was drawn, kept for `DisposalMethod::Previous`.
+    previous: Option<RgbaImage>,
+}
+
+/// Composites the frames of a GIF into full images. Frames may cover only part of the image and
+/// have transparent pixels, which show what the previous frames left on the canvas according to
+/// their disposal methods.
+pub struct Canvas {
+    image: RgbaImage,
+    disposal: Option<Disposal>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            image: RgbaImage::new(width, height),
+            disposal: None,
+        }
+    }
+
+    /// Draws a frame decoded with `gif::ColorOutput::RGBA` and returns the resulting image.
+    pub fn draw(&mut self, frame: &gif::Frame) -> RgbaImage {
+        self.dispose();
+
+        let (left, top) = (frame.left as u32, frame.top as u32);
+        let (width, height) = (frame.width as u32, frame.height as u32);
+
+        let previous = match frame.dispose {
+            DisposalMethod::Previous => Some(self.image.clone()),
+            _ => None,
+        };
+
+        for (index, pixel) in frame.buffer.chunks_exact(4).enumerate() {
+            let (x, y) = (left + index as u32 % width, top + index as u32 / width);
+            // GIF pixels are either fully opaque or fully transparent.
+            if pixel[3] != 0 && x < self.image.width() && y < self.image.height() {
+                self.image
+                    .put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+
+        self.disposal = Some(Disposal {
+            method: frame.dispose,
+            area: (left, top, width, height),
+            previous,
+        });
+
+        self.image.clone()
+    }
+
+    fn dispose(&mut self) {
+        let disposal = match self.disposal.take() {
+            Some(disposal) => disposal,
+            None => return,
+        };
+
+        match disposal.method {
+            // Browsers clear to transparent rather than to the background color, and so do we.
+            DisposalMethod::Background => {
+                let (left, top, width, height) = disposal.area;
+                let right = (left + width).min(self.image.width());
+                let bottom = (top + height).min(self.image.height());
+                for y in top..bottom {
+                    for x in left..right {
+                        self.image.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+            DisposalMethod::Previous => {
+                if let Some(previous) = disposal.previous {
+                    self.image = previous;
+                }
+            }
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    fn frame(
+        area: (u16, u16, u16, u16),
+        pixels: &[[u8; 4]],
+        dispose: DisposalMethod,
+    ) -> gif::Frame<'static> {
+        let (left, top, width, height) = area;
+        gif::Frame {
+            left,
+            top,
+            width,
+            height,
+            dispose,
+            buffer: Cow::Owned(pixels.concat()),
+            ..Default::default()
+        }
+    }
+
+    fn pixels(image: &RgbaImage) -> Vec<[u8; 4]> {
+        image.pixels().map(|pixel| pixel.0).collect()
+    }
+
+    #[test]
+    fn test_compositing() {
+        let mut canvas = Canvas::new(2, 2);
+
+        let image = canvas.draw(&frame((0, 0, 2, 2), &[RED; 4], DisposalMethod::Keep));
+        assert_eq!(pixels(&image), vec![RED; 4]);
+
+        // A sub-rectangle with a transparent pixel, the rest of the canvas shows through.
+        let image = canvas.draw(&frame(
+            (1, 0, 1, 2),
+            &[BLUE, CLEAR],
+            DisposalMethod::Previous,
+        ));
+        assert_eq!(pixels(&image), vec![RED, BLUE, RED, RED]);
+
+        // The previous frame is undone before this one is drawn.
+        let image = canvas.draw(&frame((0, 1, 1, 1), &[BLUE], DisposalMethod::Background));
+        assert_eq!(pixels(&image), vec![RED, RED, BLUE, RED]);
+
+        // The previous frame is cleared before this one is drawn.
+        let image = canvas.draw(&frame((1, 1, 1, 1), &[BLUE], DisposalMethod::Keep));
+        assert_eq!(pixels(&image), vec![RED, RED, CLEAR, BLUE]);
+    }
+}
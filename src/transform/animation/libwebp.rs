@@ -0,0 +1,392 @@
+use crate::transform::error::{DecodeError, EncodeError};
+use image::RgbaImage;
+use libwebp_sys::{
+    WebPConfig, WebPConfigInitInternal, WebPFree, WebPPicture, WebPPictureFree,
+    WebPPictureImportRGBA, WebPPictureInitInternal, WebPPreset, WEBP_ENCODER_ABI_VERSION,
+};
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+type EncodeResult<T> = Result<T, EncodeError>;
+type DecodeResult<T> = Result<T, DecodeError>;
+
+// libwebp-sys compiles the animation API of libwebp (`src/webp/mux.h` and `src/webp/demux.h`) but
+// has no bindings for it.
+mod ffi {
+    use super::*;
+
+    pub const WEBP_MUX_ABI_VERSION: c_int = 0x0108;
+    pub const WEBP_DEMUX_ABI_VERSION: c_int = 0x0107;
+    pub const MODE_RGBA: c_int = 1;
+
+    #[repr(C)]
+    pub struct WebPMuxAnimParams {
+        pub bgcolor: u32,
+        pub loop_count: c_int,
+    }
+
+    #[repr(C)]
+    pub struct WebPAnimEncoderOptions {
+        pub anim_params: WebPMuxAnimParams,
+        pub minimize_size: c_int,
+        pub kmin: c_int,
+        pub kmax: c_int,
+        pub allow_mixed: c_int,
+        pub verbose: c_int,
+        pub padding: [u32; 4],
+    }
+
+    #[repr(C)]
+    pub struct WebPData {
+        pub bytes: *const u8,
+        pub size: usize,
+    }
+
+    #[repr(C)]
+    pub struct WebPAnimDecoderOptions {
+        pub color_mode: c_int,
+        pub use_threads: c_int,
+        pub padding: [u32; 7],
+    }
+
+    #[repr(C)]
+    pub struct WebPAnimInfo {
+        pub canvas_width: u32,
+        pub canvas_height: u32,
+        pub loop_count: u32,
+        pub bgcolor: u32,
+        pub frame_count: u32,
+        pub pad: [u32; 4],
+    }
+
+    pub enum WebPAnimEncoder {}
+    pub enum WebPAnimDecoder {}
+
+    extern "C" {
+        pub fn WebPAnimEncoderOptionsInitInternal(
+            options: *mut WebPAnimEncoderOptions,
+            abi_version: c_int,
+        ) -> c_int;
+        pub fn WebPAnimEncoderNewInternal(
+            width: c_int,
+            height: c_int,
+            options: *const WebPAnimEncoderOptions,
+            abi_version: c_int,
+        ) -> *mut WebPAnimEncoder;
+        pub fn WebPAnimEncoderAdd(
+            encoder: *mut WebPAnimEncoder,
+            frame: *mut WebPPicture,
+            timestamp: c_int,
+            config: *const WebPConfig,
+        ) -> c_int;
+        pub fn WebPAnimEncoderAssemble(encoder: *mut WebPAnimEncoder, data: *mut WebPData)
+            -> c_int;
+        pub fn WebPAnimEncoderGetError(encoder: *mut WebPAnimEncoder) -> *const c_char;
+        pub fn WebPAnimEncoderDelete(encoder: *mut WebPAnimEncoder);
+
+        pub fn WebPAnimDecoderOptionsInitInternal(
+            options: *mut WebPAnimDecoderOptions,
+            abi_version: c_int,
+        ) -> c_int;
+        pub fn WebPAnimDecoderNewInternal(
+            data: *const WebPData,
+            options: *const WebPAnimDecoderOptions,
+            abi_version: c_int,
+        ) -> *mut WebPAnimDecoder;
+        pub fn WebPAnimDecoderGetInfo(
+            decoder: *const WebPAnimDecoder,
+            info: *mut WebPAnimInfo,
+        ) -> c_int;
+        pub fn WebPAnimDecoderHasMoreFrames(decoder: *const WebPAnimDecoder) -> c_int;
+        pub fn WebPAnimDecoderGetNext(
+            decoder: *mut WebPAnimDecoder,
+            buffer: *mut *mut u8,
+            timestamp: *mut c_int,
+        ) -> c_int;
+        pub fn WebPAnimDecoderDelete(decoder: *mut WebPAnimDecoder);
+    }
+}
+
+/// Decodes the frames of a WebP, composited by libwebp.
+pub struct AnimationDecoder<'a> {
+    decoder: NonNull<ffi::WebPAnimDecoder>,
+    info: ffi::WebPAnimInfo,
+
+    /// When the previous frame ended, in milliseconds.
+    timestamp: c_int,
+
+    /// libwebp reads from the input while decoding.
+    bytes: PhantomData<&'a [u8]>,
+}
+
+impl<'a> AnimationDecoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> DecodeResult<Self> {
+        unsafe {
+            let mut options: ffi::WebPAnimDecoderOptions = mem::zeroed();
+            if ffi::WebPAnimDecoderOptionsInitInternal(&mut options, ffi::WEBP_DEMUX_ABI_VERSION)
+                == 0
+            {
+                return Err(DecodeError::WebPError(
+                    "libwebp version mismatch".to_string(),
+                ));
+            }
+            options.color_mode = ffi::MODE_RGBA;
+
+            let data = ffi::WebPData {
+                bytes: bytes.as_ptr(),
+                size: bytes.len(),
+            };
+            let decoder = NonNull::new(ffi::WebPAnimDecoderNewInternal(
+                &data,
+                &options,
+                ffi::WEBP_DEMUX_ABI_VERSION,
+            ))
+            .ok_or_else(|| DecodeError::WebPError("the file is malformed".to_string()))?;
+
+            let mut info: ffi::WebPAnimInfo = mem::zeroed();
+            if ffi::WebPAnimDecoderGetInfo(decoder.as_ptr(), &mut info) == 0 {
+                ffi::WebPAnimDecoderDelete(decoder.as_ptr());
+                return Err(DecodeError::WebPError("the file is malformed".to_string()));
+            }
+
+            Ok(Self {
+                decoder,
+                info,
+                timestamp: 0,
+                bytes: PhantomData,
+            })
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.info.canvas_width, self.info.canvas_height)
+    }
+
+    /// How many times the animation is played, or 0 if it's played forever.
+    pub fn plays(&self) -> u32 {
+        self.info.loop_count
+    }
+
+    /// Decodes the next frame and how long it's shown, in milliseconds.
+    pub fn next_frame(&mut self) -> DecodeResult<Option<(RgbaImage, u32)>> {
+        let (width, height) = self.dimensions();
+
+        unsafe {
+            if ffi::WebPAnimDecoderHasMoreFrames(self.decoder.as_ptr()) == 0 {
+                return Ok(None);
+            }
+
+            let mut buffer = ptr::null_mut();
+            let mut timestamp = 0;
+            if ffi::WebPAnimDecoderGetNext(self.decoder.as_ptr(), &mut buffer, &mut timestamp) == 0
+                || buffer.is_null()
+            {
+                return Err(DecodeError::WebPError("a frame is malformed".to_string()));
+            }
+
+            // The buffer belongs to the decoder and is overwritten by the next frame.
+            let pixels = slice::from_raw_parts(buffer, width as usize * height as usize * 4);
+            let image = RgbaImage::from_raw(width, height, pixels.to_vec()).ok_or_else(|| {
+                DecodeError::WebPError("the frame doesn't fit the canvas".to_string())
+            })?;
+
+            // The timestamps are when the frames end.
+            let delay = timestamp.saturating_sub(self.timestamp).max(0) as u32;
+            self.timestamp = timestamp;
+
+            Ok(Some((image, delay)))
+        }
+    }
+}
+
+impl Drop for AnimationDecoder<'_> {
+    fn drop(&mut self) {
+        unsafe { ffi::WebPAnimDecoderDelete(self.decoder.as_ptr()) }
+    }
+}
+
+/// Encodes RGBA frames to an animated WebP. libwebp stores only what changed between frames, and
+/// picks the disposal and blending of every frame by itself.
+pub struct AnimationEncoder {
+    encoder: NonNull<ffi::WebPAnimEncoder>,
+    config: WebPConfig,
+    width: u32,
+    height: u32,
+
+    /// When the next frame is shown, in milliseconds.
+    timestamp: c_int,
+}
+
+impl AnimationEncoder {
+    /// Creates an encoder for frames of `width`×`height` pixels. The animation is played
+    /// `loop_count` times, or forever if it's 0.
+    pub fn new(width: u32, height: u32, quality: f32, loop_count: u16) -> EncodeResult<Self> {
+        unsafe {
+            let mut options: ffi::WebPAnimEncoderOptions = mem::zeroed();
+            let mut config: WebPConfig = mem::zeroed();
+
+            if ffi::WebPAnimEncoderOptionsInitInternal(&mut options, ffi::WEBP_MUX_ABI_VERSION) == 0
+                || WebPConfigInitInternal(
+                    &mut config,
+                    WebPPreset::WEBP_PRESET_DEFAULT,
+                    quality,
+                    WEBP_ENCODER_ABI_VERSION as c_int,
+                ) == 0
+            {
+                return Err(EncodeError::WebPError(
+                    "libwebp version mismatch".to_string(),
+                ));
+            }
+
+            options.anim_params.loop_count = loop_count as c_int;
+
+            let encoder = ffi::WebPAnimEncoderNewInternal(
+                width as c_int,
+                height as c_int,
+                &options,
+                ffi::WEBP_MUX_ABI_VERSION,
+            );
+
+            Ok(Self {
+                encoder: NonNull::new(encoder).ok_or_else(|| {
+                    EncodeError::WebPError("libwebp failed to create the encoder".to_string())
+                })?,
+                config,
+                width,
+                height,
+                timestamp: 0,
+            })
+        }
+    }
+
+    /// Adds a frame of RGBA pixels, shown for `duration` milliseconds.
+    pub fn add(&mut self, rgba: &[u8], duration: u32) -> EncodeResult<()> {
+        if rgba.len() != self.width as usize * self.height as usize * 4 {
+            return Err(EncodeError::WebPError(
+                "the frame doesn't have the size of the animation".to_string(),
+            ));
+        }
+
+        unsafe {
+            let mut picture: WebPPicture = mem::zeroed();
+            if WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as c_int) == 0 {
+                return Err(EncodeError::WebPError(
+                    "libwebp version mismatch".to_string(),
+                ));
+            }
+            picture.use_argb = 1;
+            picture.width = self.width as c_int;
+            picture.height = self.height as c_int;
+
+            if WebPPictureImportRGBA(&mut picture, rgba.as_ptr(), self.width as c_int * 4) == 0 {
+                WebPPictureFree(&mut picture);
+                return Err(EncodeError::WebPError(
+                    "libwebp failed to add the frame".to_string(),
+                ));
+            }
+
+            let added = ffi::WebPAnimEncoderAdd(
+                self.encoder.as_ptr(),
+                &mut picture,
+                self.timestamp,
+                &self.config,
+            );
+            WebPPictureFree(&mut picture);
+
+            if added == 0 {
+                return Err(self.error());
+            }
+        }
+
+        self.timestamp = self.timestamp.saturating_add(duration as c_int);
+        Ok(())
+    }
+
+    /// Assembles the animation. The last frame is shown for the duration it was added with.
+    pub fn finish(self) -> EncodeResult<Vec<u8>> {
+        unsafe {
+            let encoder = self.encoder.as_ptr();
+            if ffi::WebPAnimEncoderAdd(encoder, ptr::null_mut(), self.timestamp, ptr::null()) == 0 {
+                return Err(self.error());
+            }
+
+            let mut data = ffi::WebPData {
+                bytes: ptr::null(),
+                size: 0,
+            };
+            if ffi::WebPAnimEncoderAssemble(encoder, &mut data) == 0 {
+                return Err(self.error());
+            }
+
+            let bytes = slice::from_raw_parts(data.bytes, data.size).to_vec();
+            WebPFree(data.bytes as *mut c_void);
+
+            Ok(bytes)
+        }
+    }
+
+    fn error(&self) -> EncodeError {
+        let message = unsafe {
+            let message = ffi::WebPAnimEncoderGetError(self.encoder.as_ptr());
+            if message.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(message).to_string_lossy().into_owned()
+            }
+        };
+
+        EncodeError::WebPError(message)
+    }
+}
+
+impl Drop for AnimationEncoder {
+    fn drop(&mut self) {
+        unsafe { ffi::WebPAnimEncoderDelete(self.encoder.as_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_animation_round_trip() {
+        let mut encoder = AnimationEncoder::new(2, 2, 100.0, 3).unwrap();
+        encoder.add(&[255; 16], 100).unwrap();
+        encoder.add(&[0; 16], 250).unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WEBPVP8X");
+        // The animation flag of the extended header.
+        assert_ne!(bytes[20] & 0x02, 0);
+
+        let mut decoder = AnimationDecoder::new(&bytes).unwrap();
+        assert_eq!(decoder.dimensions(), (2, 2));
+        assert_eq!(decoder.plays(), 3);
+
+        let (image, delay) = decoder.next_frame().unwrap().unwrap();
+        assert_eq!((image.get_pixel(0, 0).0, delay), ([255; 4], 100));
+        let (image, delay) = decoder.next_frame().unwrap().unwrap();
+        assert_eq!((image.get_pixel(1, 1).0[3], delay), (0, 250));
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_encoder() {
+        let mut encoder = AnimationEncoder::new(2, 2, 100.0, 0).unwrap();
+        assert!(encoder.add(&[255; 12], 100).is_err());
+
+        // libwebp refuses empty animations.
+        assert!(AnimationEncoder::new(0, 0, 100.0, 0).is_err());
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        assert!(AnimationDecoder::new(b"RIFF\x04\x00\x00\x00WEBP").is_err());
+    }
+}
@@ -0,0 +1,480 @@
+pub mod apng;
+pub mod canvas;
+pub mod libwebp;
+
+use super::{
+    budget::Budget,
+    encoding,
+    error::{DecodeError, EncodeError},
+    TransformResult,
+};
+use canvas::Canvas;
+use color_quant::NeuQuant;
+use gif::{DisposalMethod, Repeat, SetParameter};
+use image::{imageops, ImageFormat, RgbaImage};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Which frame of an animation to extract.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSelection {
+    /// A zero-based index. `first` is parsed as 0.
+    Index(u64),
+    Last,
+    Middle,
+}
+
+impl FrameSelection {
+    /// The index of the selected frame of an input with `frames` frames.
+    pub fn index(self, frames: u64) -> TransformResult<u64> {
+        let index = match self {
+            FrameSelection::Index(index) => index,
+            FrameSelection::Last => frames.saturating_sub(1),
+            FrameSelection::Middle => frames / 2,
+        };
+
+        if index < frames {
+            Ok(index)
+        } else {
+            Err(DecodeError::FrameOutOfRange(index, frames).into())
+        }
+    }
+}
+
+impl FromStr for FrameSelection {
+    type Err = DecodeError;
+
+    fn from_str(selection: &str) -> Result<Self, Self::Err> {
+        match selection {
+            "first" => Ok(FrameSelection::Index(0)),
+            "last" => Ok(FrameSelection::Last),
+            "middle" => Ok(FrameSelection::Middle),
+            index => index
+                .parse()
+                .map(FrameSelection::Index)
+                .map_err(|_| DecodeError::InvalidFrameSelection(selection.to_string())),
+        }
+    }
+}
+
+/// A frame of an animation, composited and resized.
+pub struct Frame {
+    pub image: RgbaImage,
+
+    /// How long the frame is shown, in milliseconds.
+    pub delay: u32,
+
+    /// How the frame is disposed of when it's encoded to a GIF. Frames of GIFs keep their own,
+    /// others are cleared, which shows every frame exactly as composited.
+    pub dispose: DisposalMethod,
+}
+
+/// Decodes the frames of an animation into full images of the same size.
+pub enum Frames<'a> {
+    Gif {
+        decoder: Box<gif::Reader<&'a [u8]>>,
+        canvas: Canvas,
+        plays: u32,
+    },
+    Apng(apng::Frames<'a>),
+    WebP(libwebp::AnimationDecoder<'a>),
+}
+
+impl<'a> Frames<'a> {
+    pub fn new(bytes: &'a [u8], format: ImageFormat) -> TransformResult<Self> {
+        match format {
+            ImageFormat::Gif => {
+                let mut decoder = gif::Decoder::new(bytes);
+                decoder.set(gif::ColorOutput::RGBA);
+                let decoder = decoder.read_info().map_err(DecodeError::from)?;
+                let canvas = Canvas::new(decoder.width() as u32, decoder.height() as u32);
+
+                // GIFs count the repetitions after the first play.
+                let plays = match repeat(bytes) {
+                    None => 1,
+                    Some(Repeat::Infinite) => 0,
+                    Some(Repeat::Finite(count)) => count as u32 + 1,
+                };
+
+                Ok(Frames::Gif {
+                    decoder: Box::new(decoder),
+                    canvas,
+                    plays,
+                })
+            }
+            ImageFormat::Png => Ok(Frames::Apng(apng::Frames::new(bytes)?)),
+            ImageFormat::WebP => Ok(Frames::WebP(libwebp::AnimationDecoder::new(bytes)?)),
+            _ => Err(DecodeError::UnsupportedEncoding.into()),
+        }
+    }
+
+    /// The dimensions of the animation.
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Frames::Gif { decoder, .. } => (decoder.width() as u32, decoder.height() as u32),
+            Frames::Apng(frames) => frames.dimensions(),
+            Frames::WebP(decoder) => decoder.dimensions(),
+        }
+    }
+
+    /// How many times the animation is played, or 0 if it's played forever.
+    pub fn plays(&self) -> u32 {
+        match self {
+            Frames::Gif { plays, .. } => *plays,
+            Frames::Apng(frames) => frames.plays(),
+            Frames::WebP(decoder) => decoder.plays(),
+        }
+    }
+
+    /// Decodes the next frame and resizes it to `width`×`height`.
+    pub fn next(&mut self, width: u32, height: u32) -> TransformResult<Option<Frame>> {
+        let (composited, delay, dispose) = match self {
+            Frames::Gif {
+                decoder, canvas, ..
+            } => match decoder.read_next_frame().map_err(DecodeError::from)? {
+                Some(frame) => (canvas.draw(frame), frame.delay as u32 * 10, frame.dispose),
+                None => return Ok(None),
+            },
+            Frames::Apng(frames) => match frames.next_frame()? {
+                Some((image, delay)) => (image, delay, DisposalMethod::Background),
+                None => return Ok(None),
+            },
+            Frames::WebP(decoder) => match decoder.next_frame()? {
+                Some((image, delay)) => (image, delay, DisposalMethod::Background),
+                None => return Ok(None),
+            },
+        };
+
+        let image = if composited.dimensions() == (width, height) {
+            composited
+        } else {
+            imageops::thumbnail(&composited, width, height)
+        };
+
+        Ok(Some(Frame {
+            image,
+            delay,
+            dispose,
+        }))
+    }
+
+    /// Decodes the frames up to the one at `index` and returns it in the size of the animation.
+    pub fn nth_frame(&mut self, index: u64, budget: &Budget) -> TransformResult<RgbaImage> {
+        let (width, height) = self.dimensions();
+        let mut current = 0;
+
+        loop {
+            budget.check()?;
+
+            match self.next(width, height)? {
+                Some(frame) if current == index => return Ok(frame.image),
+                Some(_) => current += 1,
+                None => return Err(DecodeError::FrameOutOfRange(index, current).into()),
+            }
+        }
+    }
+}
+
+/// Encodes the frames to a GIF of `width`×`height` pixels.
+pub fn encode_gif(
+    frames: &mut Frames,
+    (width, height): (u32, u32),
+    quality: u8,
+    budget: &Budget,
+) -> TransformResult<Vec<u8>> {
+    let (ewidth, eheight) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(EncodeError::InvalidDimensions(width, height).into()),
+    };
+
+    let mut output: Vec<u8> = Vec::new();
+
+    {
+        let mut encoder =
+            gif::Encoder::new(&mut output, ewidth, eheight, &[]).map_err(EncodeError::GifError)?;
+        // Without the extension the animation is played once.
+        match frames.plays() {
+            1 => {}
+            0 => encoder
+                .set(Repeat::Infinite)
+                .map_err(EncodeError::GifError)?,
+            plays => encoder
+                .set(Repeat::Finite(u16::try_from(plays - 1).unwrap_or(u16::MAX)))
+                .map_err(EncodeError::GifError)?,
+        }
+
+        loop {
+            budget.check()?;
+
+            let frame = match frames.next(width, height)? {
+                Some(frame) => frame,
+                None => break,
+            };
+
+            // The frames cover the whole image, so with these disposal methods, pixels transparent
+            // in a composited frame are transparent on screen.
+            let mut new_frame =
+                quantize(ewidth, eheight, &frame.image, encoding::gif_speed(quality));
+            new_frame.delay = u16::try_from((frame.delay + 5) / 10).unwrap_or(u16::MAX);
+            new_frame.dispose = frame.dispose;
+
+            encoder
+                .write_frame(&new_frame)
+                .map_err(EncodeError::GifError)?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Encodes the frames to an animated WebP of `width`×`height` pixels.
+pub fn encode_webp(
+    frames: &mut Frames,
+    (width, height): (u32, u32),
+    quality: f32,
+    budget: &Budget,
+) -> TransformResult<Vec<u8>> {
+    let plays = u16::try_from(frames.plays()).unwrap_or(u16::MAX);
+    let mut encoder = libwebp::AnimationEncoder::new(width, height, quality, plays)?;
+
+    // Browsers show GIF frames of 10 ms or less for 100 ms, and so does gif2webp. APNG and WebP
+    // delays are shown as they are.
+    let min_delay = matches!(frames, Frames::Gif { .. });
+
+    loop {
+        budget.check()?;
+
+        let frame = match frames.next(width, height)? {
+            Some(frame) => frame,
+            None => break,
+        };
+
+        let delay = match frame.delay {
+            0..=10 if min_delay => 100,
+            delay => delay,
+        };
+
+        encoder.add(&frame.image, delay)?;
+    }
+
+    Ok(encoder.finish()?)
+}
+
+/// Reads the loop count of a GIF from its `NETSCAPE2.0` application extension, without decoding
+/// any frames. `None` means the extension is missing and the GIF is played once.
+pub fn repeat(bytes: &[u8]) -> Option<Repeat> {
+    // Skips the header, the logical screen descriptor and the global color table.
+    let flags = *bytes.get(10)?;
+    let mut position = 13;
+    if flags & 0x80 != 0 {
+        position += 3 << ((flags & 0x07) + 1);
+    }
+
+    loop {
+        match *bytes.get(position)? {
+            // Extension
+            0x21 => {
+                let label = *bytes.get(position + 1)?;
+                position += 2;
+                let blocks = sub_blocks(bytes, &mut position)?;
+
+                if label == 0xFF
+                    && blocks.len() >= 2
+                    && (blocks[0] == b"NETSCAPE2.0" || blocks[0] == b"ANIMEXTS1.0")
+                    && blocks[1].len() >= 3
+                    && blocks[1][0] == 1
+                {
+                    return Some(match u16::from_le_bytes([blocks[1][1], blocks[1][2]]) {
+                        0 => Repeat::Infinite,
+                        count => Repeat::Finite(count),
+                    });
+                }
+            }
+            // Image descriptor, followed by a local color table and the image data.
+            0x2C => {
+                let flags = *bytes.get(position + 9)?;
+                position += 10;
+                if flags & 0x80 != 0 {
+                    position += 3 << ((flags & 0x07) + 1);
+                }
+                // The minimum code size of the LZW data.
+                position += 1;
+                sub_blocks(bytes, &mut position)?;
+            }
+            // Trailer, or garbage.
+            _ => return None,
+        }
+    }
+}
+
+/// Reads the data sub-blocks starting at `position`, up to and including the terminator.
+fn sub_blocks<'a>(bytes: &'a [u8], position: &mut usize) -> Option<Vec<&'a [u8]>> {
+    let mut blocks = Vec::new();
+
+    loop {
+        let size = *bytes.get(*position)? as usize;
+        *position += 1;
+        if size == 0 {
+            return Some(blocks);
+        }
+        blocks.push(bytes.get(*position..*position + size)?);
+        *position += size;
+    }
+}
+
+/// Converts an RGBA image to a frame with at most 256 colors. Images with few colors keep their
+/// exact colors, others are quantized with NeuQuant, `speed` trading quality (1) for speed (30).
+/// Pixels that are less than half opaque become transparent, and transparency gets a palette entry
+/// of its own so that it never swallows an opaque color.
+pub fn quantize(width: u16, height: u16, rgba: &[u8], speed: i32) -> gif::Frame<'static> {
+    let opaque = |pixel: &[u8]| pixel[3] >= 0x80;
+    let rgb = |pixel: &[u8]| [pixel[0], pixel[1], pixel[2]];
+
+    let transparent = rgba.chunks_exact(4).any(|pixel| !opaque(pixel));
+    let colors = if transparent { 255 } else { 256 };
+
+    let mut exact = HashMap::new();
+    for pixel in rgba.chunks_exact(4).filter(|pixel| opaque(pixel)) {
+        let next = exact.len();
+        exact.entry(rgb(pixel)).or_insert(next);
+        if exact.len() > colors {
+            break;
+        }
+    }
+
+    let quantizer = if exact.len() > colors {
+        let pixels: Vec<u8> = rgba
+            .chunks_exact(4)
+            .filter(|pixel| opaque(pixel))
+            .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2], 0xFF])
+            .collect();
+        Some(NeuQuant::new(speed, colors, &pixels))
+    } else {
+        None
+    };
+
+    let mut palette = match &quantizer {
+        Some(quantizer) => quantizer.color_map_rgb(),
+        None => {
+            let mut palette = vec![0; exact.len() * 3];
+            for (color, index) in &exact {
+                palette[index * 3..][..3].copy_from_slice(color);
+            }
+            palette
+        }
+    };
+
+    let transparent_index = palette.len() / 3;
+    if transparent {
+        palette.extend_from_slice(&[0, 0, 0]);
+    }
+
+    let pixels: Vec<u8> = rgba
+        .chunks_exact(4)
+        .map(|pixel| {
+            let index = if !opaque(pixel) {
+                transparent_index
+            } else {
+                match &quantizer {
+                    Some(quantizer) => quantizer.index_of(&[pixel[0], pixel[1], pixel[2], 0xFF]),
+                    None => exact[&rgb(pixel)],
+                }
+            };
+            index as u8
+        })
+        .collect();
+
+    gif::Frame::from_palette_pixels(
+        width,
+        height,
+        &pixels,
+        &palette,
+        Some(transparent_index as u8).filter(|_| transparent),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    fn encode(repeat: Option<Repeat>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut bytes, 1, 1, &[0, 0, 0, 255, 255, 255]).unwrap();
+            if let Some(repeat) = repeat {
+                gif::SetParameter::set(&mut encoder, repeat).unwrap();
+            }
+            for _ in 0..2 {
+                encoder
+                    .write_frame(&gif::Frame {
+                        width: 1,
+                        height: 1,
+                        buffer: Cow::Owned(vec![1]),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+        }
+        bytes
+    }
+
+    /// `gif::Repeat` can't be compared.
+    fn count(repeat: Option<Repeat>) -> Option<Option<u16>> {
+        repeat.map(|repeat| match repeat {
+            Repeat::Finite(count) => Some(count),
+            Repeat::Infinite => None,
+        })
+    }
+
+    #[test]
+    fn test_frame_selection() {
+        let parse = |selection: &str| selection.parse::<FrameSelection>().ok();
+
+        assert_eq!(parse("first"), Some(FrameSelection::Index(0)));
+        assert_eq!(parse("3"), Some(FrameSelection::Index(3)));
+        assert_eq!(parse("last"), Some(FrameSelection::Last));
+        assert_eq!(parse("middle"), Some(FrameSelection::Middle));
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("second"), None);
+
+        assert_eq!(FrameSelection::Last.index(5).ok(), Some(4));
+        assert_eq!(FrameSelection::Middle.index(5).ok(), Some(2));
+        assert_eq!(FrameSelection::Middle.index(1).ok(), Some(0));
+        assert!(FrameSelection::Index(5).index(5).is_err());
+        assert!(FrameSelection::Last.index(0).is_err());
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(count(repeat(&encode(None))), None);
+        assert_eq!(
+            count(repeat(&encode(Some(Repeat::Finite(3))))),
+            Some(Some(3))
+        );
+        assert_eq!(count(repeat(&encode(Some(Repeat::Infinite)))), Some(None));
+        assert_eq!(count(repeat(b"GIF89a")), None);
+    }
+
+    #[test]
+    fn test_quantize() {
+        // Black is a color of its own, not the transparent pixel.
+        let black = [0, 0, 0, 255];
+        let frame = quantize(2, 2, &[RED, black, CLEAR, BLUE].concat(), 10);
+        let palette = frame.palette.unwrap();
+        let transparent = frame.transparent.unwrap();
+        let color = |index: u8| &palette[index as usize * 3..][..3];
+
+        assert_eq!(color(frame.buffer[0]), &RED[..3]);
+        assert_eq!(color(frame.buffer[1]), &black[..3]);
+        assert_eq!(frame.buffer[2], transparent);
+        assert_eq!(color(frame.buffer[3]), &BLUE[..3]);
+        assert_ne!(frame.buffer[1], transparent);
+    }
+}
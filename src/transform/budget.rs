@@ -0,0 +1,100 @@
+use super::{error::TransformError, TransformResult};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+/// The time a transformation may take. It is checked between frames and pipeline steps, which
+/// stop the transformation once the time is up or it has been cancelled (e.g. because the client
+/// disconnected).
+#[derive(Debug, Clone)]
+pub struct Budget {
+    time: Option<Duration>,
+    deadline: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Budget {
+    /// A budget of `time`, counted from the call to `start`.
+    pub fn new(time: Duration) -> Self {
+        Self {
+            time: Some(time),
+            deadline: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A budget that never runs out, but can still be cancelled.
+    pub fn unlimited() -> Self {
+        Self {
+            time: None,
+            deadline: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Starts counting down. Clones share the cancellation, but not the deadline.
+    pub fn start(&mut self) {
+        self.deadline = self.time.map(|time| Instant::now() + time);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns a guard that cancels the budget when dropped, so that work for a request stops when
+    /// the request is dropped.
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+
+    /// Fails if the transformation should stop.
+    pub fn check(&self) -> TransformResult<()> {
+        if self.is_cancelled() {
+            return Err(TransformError::Cancelled);
+        }
+
+        match (self.time, self.deadline) {
+            (Some(time), Some(deadline)) if Instant::now() > deadline => {
+                Err(TransformError::TimedOut(time.as_secs()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Cancels a `Budget` when dropped.
+pub struct CancelOnDrop(Budget);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::new(Duration::from_millis(10));
+        assert!(budget.check().is_ok());
+
+        budget.start();
+        assert!(budget.check().is_ok());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(budget.check(), Err(TransformError::TimedOut(_))));
+
+        let budget = Budget::unlimited();
+        let guard = budget.cancel_on_drop();
+        assert!(budget.check().is_ok());
+        drop(guard);
+        assert!(matches!(budget.check(), Err(TransformError::Cancelled)));
+    }
+}
@@ -1,8 +1,10 @@
-use super::error::EncodeError;
+use super::{animation, error::EncodeError};
 use actix_web::{http::header, HttpRequest};
 use image::{DynamicImage, GenericImageView, ImageOutputFormat};
 use mime::{Mime, IMAGE_GIF, IMAGE_JPEG, IMAGE_PNG};
+use rgb::FromSlice;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::str::FromStr;
 
 type EncodeResult<T> = Result<T, EncodeError>;
@@ -12,9 +14,19 @@ pub enum Encoding {
     Jpeg(u8),
     WebP(f32),
     Png,
-    Gif,
+    Gif(u8),
+
+    /// Quality and speed (1-10, 10 being the fastest).
+    Avif(u8, u8),
 }
 
+/// The speed of AVIF encoding unless the request asks for another one.
+pub const DEFAULT_AVIF_SPEED: u8 = 6;
+
+/// The palette quality of GIFs unless the request asks for another one. GIFs were quantized at the
+/// fastest NeuQuant speed before `quality` applied to them, so that stays the default.
+pub const DEFAULT_GIF_QUALITY: u8 = 0;
+
 impl Default for Encoding {
     fn default() -> Self {
         Self::Jpeg(85)
@@ -34,23 +46,35 @@ pub enum Serializable {
 
     #[serde(rename = "gif")]
     Gif,
+
+    #[serde(rename = "avif")]
+    Avif,
 }
 
 impl Serializable {
     /// Convert a serializable encoding to an `Encoding`.
-    pub fn to_encoding(&self, quality: Option<u8>) -> EncodeResult<Encoding> {
+    pub fn to_encoding(&self, quality: Option<u8>, speed: Option<u8>) -> EncodeResult<Encoding> {
+        let gif_quality = quality.unwrap_or(DEFAULT_GIF_QUALITY);
         let quality = quality.unwrap_or(85);
 
-        if quality <= 100 {
-            Ok(match self {
-                Serializable::Jpeg => Encoding::Jpeg(quality),
-                Serializable::WebP => Encoding::WebP(quality as f32),
-                Serializable::Png => Encoding::Png,
-                Serializable::Gif => Encoding::Gif,
-            })
-        } else {
-            Err(EncodeError::InvalidQuality(0, 100, quality))
+        if quality > 100 {
+            return Err(EncodeError::InvalidQuality(0, 100, quality));
         }
+
+        Ok(match self {
+            Serializable::Jpeg => Encoding::Jpeg(quality),
+            Serializable::WebP => Encoding::WebP(quality as f32),
+            Serializable::Png => Encoding::Png,
+            Serializable::Gif => Encoding::Gif(gif_quality),
+            Serializable::Avif => {
+                let speed = speed.unwrap_or(DEFAULT_AVIF_SPEED);
+                if !(1..=10).contains(&speed) {
+                    return Err(EncodeError::InvalidSpeed(1, 10, speed));
+                }
+
+                Encoding::Avif(quality, speed)
+            }
+        })
     }
 }
 
@@ -60,15 +84,29 @@ impl From<Encoding> for Serializable {
             Encoding::Jpeg(_) => Serializable::Jpeg,
             Encoding::WebP(_) => Serializable::WebP,
             Encoding::Png => Serializable::Png,
-            Encoding::Gif => Serializable::Gif,
+            Encoding::Gif(_) => Serializable::Gif,
+            Encoding::Avif(_, _) => Serializable::Avif,
         }
     }
 }
 
+/// The NeuQuant speed for a GIF quality: 100 is the slowest and best (1), 0 the fastest (30).
+pub fn gif_speed(quality: u8) -> i32 {
+    30 - quality.min(100) as i32 * 29 / 100
+}
+
 impl Encoding {
-    pub fn detect(req: &HttpRequest) -> Encoding {
+    /// Picks the best encoding the client accepts. AVIF is skipped for `animated` sources, since it
+    /// would keep only their first frame.
+    pub fn detect(req: &HttpRequest, animated: bool) -> Encoding {
         if let Some(accept) = req.headers().get(header::ACCEPT) {
-            if accept.to_str().unwrap_or("").contains("image/webp") {
+            let accept = accept.to_str().unwrap_or("");
+
+            if accept.contains("image/avif") && !animated {
+                return Encoding::Avif(85, DEFAULT_AVIF_SPEED);
+            }
+
+            if accept.contains("image/webp") {
                 return Encoding::WebP(85.0);
             }
         }
@@ -81,7 +119,8 @@ impl Encoding {
             Encoding::Jpeg(ref quality) => Some(ImageOutputFormat::Jpeg(*quality)),
             Encoding::WebP(_) => None,
             Encoding::Png => Some(ImageOutputFormat::Png),
-            Encoding::Gif => Some(ImageOutputFormat::Gif),
+            Encoding::Gif(_) => Some(ImageOutputFormat::Gif),
+            Encoding::Avif(_, _) => None,
         }
     }
 
@@ -90,7 +129,8 @@ impl Encoding {
             Encoding::Jpeg(_) => IMAGE_JPEG,
             Encoding::WebP(_) => Mime::from_str("image/webp").unwrap(),
             Encoding::Png => IMAGE_PNG,
-            Encoding::Gif => IMAGE_GIF,
+            Encoding::Gif(_) => IMAGE_GIF,
+            Encoding::Avif(_, _) => Mime::from_str("image/avif").unwrap(),
         }
     }
 
@@ -110,12 +150,46 @@ impl Encoding {
 
                 Ok(encoder.encode(*quality).to_vec())
             }
+            Encoding::Gif(ref quality) => {
+                let (width, height) = image.dimensions();
+                let (ewidth, eheight) = match (u16::try_from(width), u16::try_from(height)) {
+                    (Ok(width), Ok(height)) => (width, height),
+                    _ => return Err(EncodeError::InvalidDimensions(width, height)),
+                };
+
+                let frame =
+                    animation::quantize(ewidth, eheight, &image.to_rgba8(), gif_speed(*quality));
+
+                let mut bytes: Vec<u8> = Vec::new();
+                gif::Encoder::new(&mut bytes, ewidth, eheight, &[])
+                    .and_then(|mut encoder| encoder.write_frame(&frame))
+                    .map_err(EncodeError::GifError)?;
+
+                Ok(bytes)
+            }
+            Encoding::Avif(ref quality, ref speed) => {
+                let (width, height) = image.dimensions();
+                let rgba = image.to_rgba8();
+
+                // ravif panics on a quality of 0.
+                let encoded = ravif::Encoder::new()
+                    .with_quality((*quality).max(1) as f32)
+                    .with_speed(*speed)
+                    .encode_rgba(ravif::Img::new(
+                        rgba.as_raw().as_rgba(),
+                        width as usize,
+                        height as usize,
+                    ))
+                    .map_err(|err| EncodeError::AvifError(err.to_string()))?;
+
+                Ok(encoded.avif_file)
+            }
             _ => {
                 let mut bytes: Vec<u8> = Vec::new();
                 image.write_to(
                     &mut bytes,
                     self.image_output_format()
-                        .unwrap_or_else(|| ImageOutputFormat::Jpeg(85)),
+                        .unwrap_or(ImageOutputFormat::Jpeg(85)),
                 )?;
 
                 Ok(bytes)
@@ -134,8 +208,70 @@ mod tests {
         let serializable = Serializable::WebP;
 
         assert_eq!(
-            serializable.to_encoding(Some(69)).unwrap().mime_type(),
+            serializable
+                .to_encoding(Some(69), None)
+                .unwrap()
+                .mime_type(),
             Encoding::WebP(69.0).mime_type()
         );
     }
+
+    #[test]
+    fn test_avif_speed() {
+        match Serializable::Avif.to_encoding(None, None) {
+            Ok(Encoding::Avif(85, DEFAULT_AVIF_SPEED)) => {}
+            encoding => panic!("unexpected encoding: {:?}", encoding.ok()),
+        }
+
+        assert!(Serializable::Avif.to_encoding(None, Some(0)).is_err());
+        assert!(Serializable::Avif.to_encoding(None, Some(11)).is_err());
+
+        // Only AVIF has a speed, the others ignore it.
+        assert!(Serializable::Jpeg.to_encoding(None, Some(0)).is_ok());
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |accept, animated| {
+            let req = actix_web::test::TestRequest::default()
+                .header(header::ACCEPT, accept)
+                .to_http_request();
+            Encoding::detect(&req, animated).mime_type().to_string()
+        };
+
+        assert_eq!(detect("image/avif,image/webp,*/*", false), "image/avif");
+        assert_eq!(detect("image/avif,image/webp,*/*", true), "image/webp");
+        assert_eq!(detect("image/webp,*/*", false), "image/webp");
+        assert_eq!(detect("*/*", false), "image/jpeg");
+    }
+
+    #[test]
+    fn test_encode_avif() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            8,
+            8,
+            image::Rgba([255, 0, 0, 128]),
+        ));
+        let bytes = Encoding::Avif(0, 10).encode_dynimage(&image).unwrap();
+
+        assert_eq!(&bytes[4..12], b"ftypavif");
+    }
+
+    #[test]
+    fn test_gif_speed() {
+        assert_eq!(gif_speed(100), 1);
+        assert_eq!(gif_speed(0), 30);
+        assert!(gif_speed(85) < gif_speed(50));
+    }
+
+    #[test]
+    fn test_gif_default_quality() {
+        let speed = |quality| match Serializable::Gif.to_encoding(quality, None).unwrap() {
+            Encoding::Gif(quality) => gif_speed(quality),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(speed(None), 30);
+        assert_eq!(speed(Some(85)), gif_speed(85));
+    }
 }
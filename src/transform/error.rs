@@ -11,6 +11,21 @@ pub enum EncodeError {
 
     #[fail(display = "invalid quality number (range: {}-{}, got: {})", _0, _1, _2)]
     InvalidQuality(u8, u8, u8),
+
+    #[fail(display = "invalid speed number (range: {}-{}, got: {})", _0, _1, _2)]
+    InvalidSpeed(u8, u8, u8),
+
+    #[fail(display = "unable to encode gif: {}", _0)]
+    GifError(#[cause] std::io::Error),
+
+    #[fail(display = "the output can't be {}×{} pixels", _0, _1)]
+    InvalidDimensions(u32, u32),
+
+    #[fail(display = "unable to encode webp: {}", _0)]
+    WebPError(String),
+
+    #[fail(display = "unable to encode avif: {}", _0)]
+    AvifError(String),
 }
 
 impl EncodeError {
@@ -19,6 +34,11 @@ impl EncodeError {
             EncodeError::ImageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             EncodeError::UnsupportedEncoding => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             EncodeError::InvalidQuality(_, _, _) => StatusCode::BAD_REQUEST,
+            EncodeError::InvalidSpeed(_, _, _) => StatusCode::BAD_REQUEST,
+            EncodeError::GifError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EncodeError::InvalidDimensions(_, _) => StatusCode::BAD_REQUEST,
+            EncodeError::WebPError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EncodeError::AvifError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
@@ -36,6 +56,30 @@ pub enum DecodeError {
 
     #[fail(display = "unsupported encoding")]
     UnsupportedEncoding,
+
+    #[fail(display = "the input has too many pixels (got: {}, limit: {})", _0, _1)]
+    TooManyPixels(u64, u64),
+
+    #[fail(display = "invalid gif: {}", _0)]
+    GifError(#[cause] gif::DecodingError),
+
+    #[fail(
+        display = "frame {} of the animation has an unsupported position or size",
+        _0
+    )]
+    InvalidFrame(usize),
+
+    #[fail(display = "invalid webp: {}", _0)]
+    WebPError(String),
+
+    #[fail(
+        display = "invalid frame (expected an index, first, last or middle, got: {})",
+        _0
+    )]
+    InvalidFrameSelection(String),
+
+    #[fail(display = "the input has no frame {} (frames: {})", _0, _1)]
+    FrameOutOfRange(u64, u64),
 }
 
 impl DecodeError {
@@ -43,10 +87,22 @@ impl DecodeError {
         match self {
             DecodeError::ImageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             DecodeError::UnsupportedEncoding => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            DecodeError::TooManyPixels(_, _) => StatusCode::UNPROCESSABLE_ENTITY,
+            DecodeError::GifError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DecodeError::InvalidFrame(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DecodeError::WebPError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DecodeError::InvalidFrameSelection(_) => StatusCode::BAD_REQUEST,
+            DecodeError::FrameOutOfRange(_, _) => StatusCode::UNPROCESSABLE_ENTITY,
         }
     }
 }
 
+impl From<gif::DecodingError> for DecodeError {
+    fn from(err: gif::DecodingError) -> DecodeError {
+        DecodeError::GifError(err)
+    }
+}
+
 impl From<image::ImageError> for DecodeError {
     fn from(err: image::ImageError) -> DecodeError {
         DecodeError::ImageError(err)
@@ -60,6 +116,18 @@ pub enum TransformError {
 
     #[fail(display = "{}", _0)]
     DecodeError(#[cause] DecodeError),
+
+    #[fail(display = "too many transformations in progress, retry in {} s", _0)]
+    Overloaded(u64),
+
+    #[fail(display = "the transformation failed unexpectedly")]
+    Crashed,
+
+    #[fail(display = "the transformation took longer than {} s", _0)]
+    TimedOut(u64),
+
+    #[fail(display = "the transformation was cancelled")]
+    Cancelled,
 }
 
 impl TransformError {
@@ -67,6 +135,18 @@ impl TransformError {
         match self {
             TransformError::EncodeError(err) => err.status_code(),
             TransformError::DecodeError(err) => err.status_code(),
+            TransformError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            TransformError::Crashed => StatusCode::INTERNAL_SERVER_ERROR,
+            TransformError::TimedOut(_) => StatusCode::SERVICE_UNAVAILABLE,
+            TransformError::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Seconds after which the client may retry, if the error is temporary.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            TransformError::Overloaded(retry_after) => Some(*retry_after),
+            _ => None,
         }
     }
 }
@@ -9,6 +9,7 @@ pub struct DimensionLimits {
     pub webp: Option<DimensionLimit>,
     pub png: Option<DimensionLimit>,
     pub gif: Option<DimensionLimit>,
+    pub avif: Option<DimensionLimit>,
 
     /// The default limit used as a fallback for the formats.
     pub default: DimensionLimit,
@@ -20,8 +21,39 @@ impl DimensionLimits {
             Encoding::Jpeg(_) => self.jpeg,
             Encoding::WebP(_) => self.webp,
             Encoding::Png => self.png,
-            Encoding::Gif => self.gif,
+            Encoding::Gif(_) => self.gif,
+            Encoding::Avif(_, _) => self.avif,
+        }
+        .unwrap_or(self.default)
+    }
+}
+
+/// Limits on the size of inputs, checked before they are decoded so that a small file declaring
+/// huge dimensions (a decompression bomb) can't exhaust the memory.
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct InputLimits {
+    /// The maximum number of pixels of a single frame.
+    #[serde(default = "default_max_pixels")]
+    pub max_pixels: u64,
+
+    /// The maximum number of pixels of all frames of an animation together.
+    #[serde(default = "default_max_animated_pixels")]
+    pub max_animated_pixels: u64,
+}
+
+fn default_max_pixels() -> u64 {
+    8192 * 8192
+}
+
+fn default_max_animated_pixels() -> u64 {
+    4 * default_max_pixels()
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            max_pixels: default_max_pixels(),
+            max_animated_pixels: default_max_animated_pixels(),
         }
-        .unwrap_or_else(|| self.default)
     }
 }
@@ -1,72 +1,212 @@
+pub mod animation;
+pub mod budget;
 pub mod encoding;
 pub mod error;
 pub mod limit;
+pub mod pool;
+pub mod probe;
 pub mod resize;
 
 use encoding::Encoding;
 use error::{DecodeError, TransformError};
-use gif::SetParameter;
-use image::{DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+use image::{DynamicImage, ImageFormat};
 
 pub type TransformResult<T> = Result<T, TransformError>;
 
-/// Transform a byte vector to another byte vector. This function guesses the encoding based on the data and converts it to another format with new dimensions.
+/// Transform a byte vector to another byte vector. This function guesses the encoding based on the data and converts it to another format with new dimensions. If a frame is selected, only that frame of an animation is kept.
 pub fn transform_vec(
     bytes: Vec<u8>,
     dimensions: (Option<u32>, Option<u32>),
+    frame: Option<animation::FrameSelection>,
     target: &Encoding,
     limits: &limit::DimensionLimits,
+    input_limits: &limit::InputLimits,
+    budget: &budget::Budget,
 ) -> TransformResult<Vec<u8>> {
+    // The transformation may have waited in a queue for a while.
+    budget.check()?;
+
     let limit = limits.get(target);
 
-    match (
-        image::guess_format(&bytes).map_err(|_| DecodeError::UnsupportedEncoding)?,
-        target,
-    ) {
-        (ImageFormat::Gif, Encoding::Gif) => {
-            let mut decoder = gif::Decoder::new(bytes.as_slice());
-            decoder.set(gif::ColorOutput::RGBA);
-            let mut decoder = decoder
-                .read_info()
-                .map_err(|_| DecodeError::UnsupportedEncoding)?;
+    let format = image::guess_format(&bytes).map_err(|_| DecodeError::UnsupportedEncoding)?;
 
-            let (owidth, oheight) = (decoder.width() as u32, decoder.height() as u32);
+    let probe = probe::Probe::read(&bytes, format, input_limits)?;
 
-            let (nwidth, nheight) = resize::dimensions((owidth, oheight), dimensions, limit, false);
+    // Targets that can't be animated get the first frame.
+    let frame = frame.or(match target {
+        Encoding::Gif(_) | Encoding::WebP(_) => None,
+        _ => Some(animation::FrameSelection::Index(0)),
+    });
 
-            let mut output: Vec<u8> = Vec::new();
+    if let Some(frame) = frame {
+        let index = frame.index(probe.frames)?;
 
-            {
-                let mut encoder =
-                    gif::Encoder::new(&mut output, nwidth as u16, nheight as u16, &[]).unwrap();
-                encoder.set(gif::Repeat::Infinite).unwrap();
-
-                while let Some(frame) = decoder.read_next_frame().unwrap() {
-                    let rgba = match RgbaImage::from_raw(owidth, oheight, frame.buffer.to_vec()) {
-                        Some(buffer) => buffer,
-                        None => panic!(),
-                    };
-                    let resized = DynamicImage::ImageRgba8(rgba).thumbnail_exact(nwidth, nheight);
-                    let mut new_rgba = resized.to_rgba().to_vec();
-                    let (width, height) = resized.dimensions();
-
-                    let frame = gif::Frame::from_rgba_speed(
-                        width as u16,
-                        height as u16,
-                        &mut *new_rgba,
-                        30,
-                    );
-
-                    encoder.write_frame(&frame).unwrap();
-                }
-            }
+        if probe.frames > 1 {
+            let image = animation::Frames::new(&bytes, format)?.nth_frame(index, budget)?;
+            let resized = resize::dynimage(DynamicImage::ImageRgba8(image), dimensions, limit)?;
+            budget.check()?;
+            return Ok(target.encode_dynimage(&resized)?);
+        }
+    }
 
-            Ok(output)
+    match target {
+        // GIFs are always re-encoded frame by frame to keep their palette and timing.
+        Encoding::Gif(quality) if probe.frames > 1 || format == ImageFormat::Gif => {
+            let mut frames = animation::Frames::new(&bytes, format)?;
+            let new_dimensions = resize::dimensions(frames.dimensions(), dimensions, limit, false);
+            animation::encode_gif(&mut frames, new_dimensions, *quality, budget)
+        }
+        Encoding::WebP(quality) if probe.frames > 1 => {
+            let mut frames = animation::Frames::new(&bytes, format)?;
+            let new_dimensions = resize::dimensions(frames.dimensions(), dimensions, limit, false);
+            animation::encode_webp(&mut frames, new_dimensions, *quality, budget)
         }
         _ => {
-            let dynamic_image = image::load_from_memory(&bytes).map_err(DecodeError::ImageError)?;
+            let dynamic_image = decode_still(&bytes, format, budget)?;
+            budget.check()?;
             let resized = resize::dynimage(dynamic_image, dimensions, limit)?;
+            budget.check()?;
             Ok(target.encode_dynimage(&resized)?)
         }
     }
 }
+
+/// Decodes the only (or first) frame of an input. The `image` crate can't read the extended WebP
+/// format, which stills use as well (e.g. for transparency), so those are decoded by libwebp.
+fn decode_still(
+    bytes: &[u8],
+    format: ImageFormat,
+    budget: &budget::Budget,
+) -> TransformResult<DynamicImage> {
+    match bytes.get(12..21) {
+        Some(header) if format == ImageFormat::WebP && header.starts_with(b"VP8X") => {
+            // The animation flag of the extended header.
+            if header[8] & 0x02 != 0 {
+                let image = animation::Frames::new(bytes, format)?.nth_frame(0, budget)?;
+                return Ok(DynamicImage::ImageRgba8(image));
+            }
+
+            webp::Decoder::new(bytes)
+                .decode()
+                .map(|image| image.to_image())
+                .ok_or_else(|| DecodeError::WebPError("the file is malformed".to_string()).into())
+        }
+        _ => Ok(image::load_from_memory(bytes).map_err(DecodeError::ImageError)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::SetParameter;
+    use std::borrow::Cow;
+
+    /// A 4×4 GIF played three times: red for 70 ms, then blue for 200 ms.
+    fn animated_gif() -> Vec<u8> {
+        let mut source = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut source, 4, 4, &[255, 0, 0, 0, 0, 255]).unwrap();
+            encoder.set(gif::Repeat::Finite(2)).unwrap();
+            for (delay, dispose) in [
+                (7, gif::DisposalMethod::Keep),
+                (20, gif::DisposalMethod::Background),
+            ]
+            .iter()
+            {
+                encoder
+                    .write_frame(&gif::Frame {
+                        width: 4,
+                        height: 4,
+                        delay: *delay,
+                        dispose: *dispose,
+                        buffer: Cow::Owned(vec![(*delay % 2) as u8; 16]),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+        }
+        source
+    }
+
+    fn transform(target: Encoding) -> Vec<u8> {
+        let limits = limit::DimensionLimits {
+            jpeg: None,
+            webp: None,
+            png: None,
+            gif: None,
+            avif: None,
+            default: (100, 100),
+        };
+        transform_vec(
+            animated_gif(),
+            (Some(2), None),
+            None,
+            &target,
+            &limits,
+            &limit::InputLimits::default(),
+            &budget::Budget::unlimited(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gif_animation_is_preserved() {
+        let output = transform(Encoding::Gif(85));
+
+        match animation::repeat(&output) {
+            Some(gif::Repeat::Finite(2)) => {}
+            _ => panic!("the loop count was not preserved"),
+        }
+
+        let mut decoder = gif::Decoder::new(output.as_slice());
+        decoder.set(gif::ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (2, 2));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.dispose, frame.buffer[..4].to_vec()));
+        }
+        assert_eq!(
+            frames,
+            vec![
+                (7, gif::DisposalMethod::Keep, vec![0, 0, 255, 255]),
+                (20, gif::DisposalMethod::Background, vec![255, 0, 0, 255]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gif_to_animated_webp() {
+        let output = transform(Encoding::WebP(80.0));
+        assert_eq!(&output[..4], b"RIFF");
+
+        // The chunks following the RIFF header.
+        let mut chunks = Vec::new();
+        let mut position = 12;
+        while position + 8 <= output.len() {
+            let size = u32::from_le_bytes([
+                output[position + 4],
+                output[position + 5],
+                output[position + 6],
+                output[position + 7],
+            ]) as usize;
+            chunks.push((
+                &output[position..position + 4],
+                &output[position + 8..position + 8 + size],
+            ));
+            position += 8 + size + size % 2;
+        }
+
+        let anim = chunks.iter().find(|(id, _)| id == b"ANIM").unwrap().1;
+        assert_eq!(u16::from_le_bytes([anim[4], anim[5]]), 3);
+
+        let durations: Vec<u32> = chunks
+            .iter()
+            .filter(|(id, _)| id == b"ANMF")
+            .map(|(_, frame)| u32::from_le_bytes([frame[12], frame[13], frame[14], 0]))
+            .collect();
+        assert_eq!(durations, vec![70, 200]);
+    }
+}
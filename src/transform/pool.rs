@@ -0,0 +1,119 @@
+use super::{error::TransformError, TransformResult};
+use crate::settings;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads running transformations, so that decoding and encoding never block
+/// the workers serving requests. Jobs wait in a bounded queue, and are rejected right away when it
+/// is full instead of piling up.
+#[derive(Clone)]
+pub struct Pool {
+    queue: SyncSender<Job>,
+
+    /// Seconds clients are asked to wait before retrying when the queue is full.
+    retry_after: u64,
+}
+
+impl Pool {
+    pub fn new(threads: usize, queue_size: usize, retry_after: u64) -> io::Result<Self> {
+        let (queue, jobs) = mpsc::sync_channel::<Job>(queue_size);
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        for index in 0..threads.max(1) {
+            let jobs = jobs.clone();
+            thread::Builder::new()
+                .name(format!("pxcmprs-transform-{}", index))
+                .spawn(move || work(&jobs))?;
+        }
+
+        Ok(Self { queue, retry_after })
+    }
+
+    pub fn from_settings(options: &settings::Transform) -> io::Result<Self> {
+        Self::new(options.threads, options.queue_size, options.retry_after)
+    }
+
+    /// Runs `job` on one of the threads of the pool. The job is queued (or rejected) right away,
+    /// the returned future resolves to its result.
+    pub fn run<T, F>(&self, job: F) -> impl Future<Output = TransformResult<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> TransformResult<T> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+
+        let queued = self
+            .queue
+            .try_send(Box::new(move || {
+                let _ = sender.send(job());
+            }))
+            .map_err(|err| match err {
+                TrySendError::Full(_) => TransformError::Overloaded(self.retry_after),
+                TrySendError::Disconnected(_) => TransformError::Crashed,
+            });
+
+        async move {
+            queued?;
+            // The sender is only dropped without a result if the job panicked.
+            receiver.await.map_err(|_| TransformError::Crashed)?
+        }
+    }
+}
+
+fn work(jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        // A panicking job must not take the thread down with it.
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_pool() {
+        let pool = Pool::new(1, 1, 5).unwrap();
+
+        assert_eq!(pool.run(|| Ok(1 + 1)).await.unwrap(), 2);
+
+        // The thread survives panicking jobs.
+        assert!(matches!(
+            pool.run(|| -> TransformResult<()> { panic!() }).await,
+            Err(TransformError::Crashed)
+        ));
+        assert_eq!(pool.run(|| Ok(3)).await.unwrap(), 3);
+
+        // One job running and one waiting, the next one is turned away.
+        let (started, running) = mpsc::channel();
+        let slow = move || {
+            thread::sleep(Duration::from_millis(50));
+            Ok(())
+        };
+        let first = pool.run(move || {
+            started.send(()).unwrap();
+            slow()
+        });
+        running.recv().unwrap();
+        let second = pool.run(slow);
+        assert!(matches!(
+            pool.run(slow).await,
+            Err(TransformError::Overloaded(5))
+        ));
+        assert!(first.await.is_ok());
+        assert!(second.await.is_ok());
+    }
+}
@@ -0,0 +1,421 @@
+use super::{error::DecodeError, limit::InputLimits, TransformResult};
+use image::{io::Reader, ImageFormat};
+use std::io::Cursor;
+
+/// What the headers of an input tell about its size.
+#[derive(Debug, PartialEq)]
+pub struct Probe {
+    pub width: u32,
+    pub height: u32,
+    pub frames: u64,
+}
+
+impl Probe {
+    /// Reads the dimensions and the number of frames of an input without decoding any pixels, and
+    /// rejects inputs that would take up too much memory once decoded.
+    pub fn read(bytes: &[u8], format: ImageFormat, limits: &InputLimits) -> TransformResult<Self> {
+        let probe = match format {
+            // Checks the limits on its own, as soon as the screen and every frame is read.
+            ImageFormat::Gif => return Self::gif(bytes, limits),
+            ImageFormat::Png => {
+                // Checked first, the decoder rejects some invalid frames with a vaguer error.
+                let frames = Self::apng_frames(bytes)?;
+                let (width, height) = Self::dimensions(bytes, format)?;
+
+                Self {
+                    width,
+                    height,
+                    frames,
+                }
+            }
+            // The extended format, which animations use, isn't supported by the `image` crate.
+            ImageFormat::WebP if bytes.get(12..16) == Some(b"VP8X") => {
+                let header = bytes
+                    .get(20..30)
+                    .ok_or_else(|| DecodeError::WebPError("the header is truncated".to_string()))?;
+                let width = u32::from_le_bytes([header[4], header[5], header[6], 0]) + 1;
+                let height = u32::from_le_bytes([header[7], header[8], header[9], 0]) + 1;
+
+                let frames = riff_chunks(bytes)
+                    .filter(|(kind, _)| kind == b"ANMF")
+                    .count() as u64;
+
+                Self {
+                    width,
+                    height,
+                    frames: frames.max(1),
+                }
+            }
+            _ => {
+                let (width, height) = Self::dimensions(bytes, format)?;
+
+                Self {
+                    width,
+                    height,
+                    frames: 1,
+                }
+            }
+        };
+
+        probe.check(limits)?;
+        Ok(probe)
+    }
+
+    /// Reads the logical screen and the frames of a GIF from its blocks, skipping over the image
+    /// data without decoding it.
+    fn gif(bytes: &[u8], limits: &InputLimits) -> TransformResult<Self> {
+        let invalid = |message| DecodeError::from(gif::DecodingError::Format(message));
+
+        if bytes.len() < 13 || !(bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")) {
+            return Err(invalid("malformed GIF header").into());
+        }
+
+        // Decoders grow the screen to fit frames larger than it, the header has the declared size.
+        let mut probe = Self {
+            width: u16::from_le_bytes([bytes[6], bytes[7]]) as u32,
+            height: u16::from_le_bytes([bytes[8], bytes[9]]) as u32,
+            frames: 0,
+        };
+        probe.check(limits)?;
+
+        let mut position = 13 + color_table_size(bytes[10]);
+        loop {
+            match bytes.get(position) {
+                // Extension: a label followed by data sub-blocks.
+                Some(0x21) => {
+                    position += 2;
+                    skip_sub_blocks(bytes, &mut position)
+                        .ok_or_else(|| invalid("truncated extension"))?;
+                }
+                // Image descriptor, followed by a local color table and the image data.
+                Some(0x2C) => {
+                    let descriptor = bytes
+                        .get(position + 1..position + 10)
+                        .ok_or_else(|| invalid("truncated image descriptor"))?;
+                    let field = |offset: usize| {
+                        u16::from_le_bytes([descriptor[offset], descriptor[offset + 1]]) as u32
+                    };
+
+                    // Decoders don't agree on (and some panic on) frames reaching outside the image.
+                    if field(0) + field(4) > probe.width || field(2) + field(6) > probe.height {
+                        return Err(DecodeError::InvalidFrame(probe.frames as usize).into());
+                    }
+
+                    // Skips the minimum code size of the LZW data as well.
+                    position += 10 + color_table_size(descriptor[8]) + 1;
+                    skip_sub_blocks(bytes, &mut position)
+                        .ok_or_else(|| invalid("truncated image data"))?;
+
+                    probe.frames += 1;
+                    probe.check(limits)?;
+                }
+                // Trailer, or the end of a file lacking one.
+                Some(0x3B) | None => return Ok(probe),
+                Some(_) => return Err(invalid("unknown block").into()),
+            }
+        }
+    }
+
+    /// Counts the frames of an APNG, or returns 1 for other PNGs, and rejects frames reaching
+    /// outside the image.
+    fn apng_frames(bytes: &[u8]) -> TransformResult<u64> {
+        let field = |data: &[u8], offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|field| u32::from_be_bytes([field[0], field[1], field[2], field[3]]) as u64)
+                .unwrap_or(0)
+        };
+
+        let mut chunks = png_chunks(bytes);
+        let (width, height) = match chunks.next() {
+            Some((kind, data)) if kind == b"IHDR" => (field(data, 0), field(data, 4)),
+            _ => return Ok(1),
+        };
+
+        let mut frames = 0;
+        // The frame control chunk of every frame.
+        for (_, data) in chunks.filter(|(kind, _)| kind == b"fcTL") {
+            let (frame_width, frame_height) = (field(data, 4), field(data, 8));
+            let (left, top) = (field(data, 12), field(data, 16));
+
+            if frame_width == 0
+                || frame_height == 0
+                || left + frame_width > width
+                || top + frame_height > height
+            {
+                return Err(DecodeError::InvalidFrame(frames as usize).into());
+            }
+            frames += 1;
+        }
+
+        Ok(frames.max(1))
+    }
+
+    fn dimensions(bytes: &[u8], format: ImageFormat) -> TransformResult<(u32, u32)> {
+        let mut reader = Reader::new(Cursor::new(bytes));
+        reader.set_format(format);
+        Ok(reader.into_dimensions().map_err(DecodeError::from)?)
+    }
+
+    /// Rejects inputs that would take up too much memory once decoded.
+    pub fn check(&self, limits: &InputLimits) -> TransformResult<()> {
+        let pixels = self.width as u64 * self.height as u64;
+        if pixels > limits.max_pixels {
+            return Err(DecodeError::TooManyPixels(pixels, limits.max_pixels).into());
+        }
+
+        let animated_pixels = pixels.saturating_mul(self.frames);
+        if animated_pixels > limits.max_animated_pixels {
+            return Err(
+                DecodeError::TooManyPixels(animated_pixels, limits.max_animated_pixels).into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The size of the color table announced by the flags of a GIF screen or image descriptor.
+fn color_table_size(flags: u8) -> usize {
+    if flags & 0x80 != 0 {
+        3 << ((flags & 0x07) + 1)
+    } else {
+        0
+    }
+}
+
+/// Skips the data sub-blocks of a GIF starting at `position`, up to and including the terminator.
+fn skip_sub_blocks(bytes: &[u8], position: &mut usize) -> Option<()> {
+    loop {
+        let size = *bytes.get(*position)? as usize;
+        *position += 1 + size;
+        if size == 0 {
+            return Some(());
+        }
+    }
+}
+
+/// The chunks of a PNG: their types and data. Stops at the end of the input or at a truncated chunk.
+pub fn png_chunks(bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut position = 8;
+
+    std::iter::from_fn(move || {
+        let length = bytes.get(position..position + 4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let kind = bytes.get(position + 4..position + 8)?;
+        let data = bytes.get(position + 8..(position + 8).checked_add(length)?)?;
+        // Followed by a CRC.
+        position += 12 + length;
+        Some((kind, data))
+    })
+}
+
+/// The chunks of a WebP following the RIFF header.
+fn riff_chunks(bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut position = 12;
+
+    std::iter::from_fn(move || {
+        let kind = bytes.get(position..position + 4)?;
+        let length = bytes.get(position + 4..position + 8)?;
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let data = bytes.get(position + 8..(position + 8).checked_add(length)?)?;
+        // Chunks are padded to an even length.
+        position += 8 + length + length % 2;
+        Some((kind, data))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::error::TransformError;
+
+    /// A PNG consisting of nothing but a header declaring its dimensions and empty image data.
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        png_with_chunks(width, height, Vec::new())
+    }
+
+    /// An APNG header with the frame control chunks of frames of the given area.
+    fn apng_header(width: u32, height: u32, frames: &[(u32, u32, u32, u32)]) -> Vec<u8> {
+        let mut chunks = vec![(*b"acTL", big_endian(&[frames.len() as u32, 0]))];
+        for (index, (left, top, frame_width, frame_height)) in frames.iter().enumerate() {
+            let mut fctl = big_endian(&[index as u32, *frame_width, *frame_height, *left, *top]);
+            fctl.extend_from_slice(&[0, 1, 0, 100, 0, 0]);
+            chunks.push((*b"fcTL", fctl));
+        }
+        png_with_chunks(width, height, chunks)
+    }
+
+    fn big_endian(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    fn png_with_chunks(width: u32, height: u32, chunks: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut all = vec![(*b"IHDR", ihdr)];
+        all.extend(chunks);
+        all.push((*b"IDAT", Vec::new()));
+        all.push((*b"IEND", Vec::new()));
+        for (name, data) in &all {
+            let mut chunk = name.to_vec();
+            chunk.extend_from_slice(data);
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(&chunk);
+            png.extend_from_slice(&crc32(&chunk).to_be_bytes());
+        }
+        png
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+            }
+        }
+        !crc
+    }
+
+    fn gif(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut output, width, height, &[]).unwrap();
+            for _ in 0..frames {
+                let mut pixels = vec![0; width as usize * height as usize * 4];
+                encoder
+                    .write_frame(&gif::Frame::from_rgba(width, height, &mut pixels))
+                    .unwrap();
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_probe() {
+        let limits = InputLimits {
+            max_pixels: 100 * 100,
+            max_animated_pixels: 3 * 100 * 100,
+        };
+
+        // A decompression bomb: tiny file, huge dimensions.
+        assert!(matches!(
+            Probe::read(&png_header(60000, 60000), ImageFormat::Png, &limits),
+            Err(TransformError::DecodeError(DecodeError::TooManyPixels(
+                3_600_000_000,
+                10000
+            )))
+        ));
+
+        let small = Probe::read(&png_header(100, 100), ImageFormat::Png, &limits).unwrap();
+        assert_eq!(
+            small,
+            Probe {
+                width: 100,
+                height: 100,
+                frames: 1
+            }
+        );
+
+        let animation = Probe::read(&gif(100, 100, 3), ImageFormat::Gif, &limits).unwrap();
+        assert_eq!(animation.frames, 3);
+
+        assert!(matches!(
+            Probe::read(&gif(100, 100, 4), ImageFormat::Gif, &limits),
+            Err(TransformError::DecodeError(DecodeError::TooManyPixels(
+                40000, 30000
+            )))
+        ));
+
+        // Rejected as soon as the limit is passed, before the garbage following the fourth frame.
+        let mut bomb = gif(100, 100, 4);
+        bomb.pop();
+        bomb.extend_from_slice(&[0x2C; 16]);
+        assert!(matches!(
+            Probe::read(&bomb, ImageFormat::Gif, &limits),
+            Err(TransformError::DecodeError(DecodeError::TooManyPixels(
+                40000, 30000
+            )))
+        ));
+
+        // The screen is checked before any frame.
+        let mut bomb = gif(1, 1, 1);
+        bomb[6..10].copy_from_slice(&[0xFF; 4]);
+        assert!(matches!(
+            Probe::read(&bomb, ImageFormat::Gif, &limits),
+            Err(TransformError::DecodeError(DecodeError::TooManyPixels(
+                4_294_836_225,
+                10000
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_probe_gif_structure() {
+        let limits = InputLimits::default();
+
+        // The image data (garbage here) is skipped without being decoded.
+        let mut garbage = b"GIF89a\x0a\x00\x0a\x00\x00\x00\x00".to_vec();
+        garbage.extend_from_slice(b"\x2c\x00\x00\x00\x00\x0a\x00\x0a\x00\x00");
+        garbage.extend_from_slice(b"\x02\x04\xff\xff\xff\xff\x00\x3b");
+        assert_eq!(
+            Probe::read(&garbage, ImageFormat::Gif, &limits).unwrap(),
+            Probe {
+                width: 10,
+                height: 10,
+                frames: 1
+            }
+        );
+
+        let gif = gif(10, 10, 2);
+        assert!(matches!(
+            Probe::read(&gif[..gif.len() - 5], ImageFormat::Gif, &limits),
+            Err(TransformError::DecodeError(DecodeError::GifError(_)))
+        ));
+        assert!(matches!(
+            Probe::read(&gif[..12], ImageFormat::Gif, &limits),
+            Err(TransformError::DecodeError(DecodeError::GifError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_probe_animations() {
+        let apng = apng_header(100, 50, &[(0, 0, 100, 50), (10, 10, 90, 40)]);
+        assert_eq!(
+            Probe::read(&apng, ImageFormat::Png, &InputLimits::default()).unwrap(),
+            Probe {
+                width: 100,
+                height: 50,
+                frames: 2
+            }
+        );
+
+        // The second frame reaches outside the image.
+        let apng = apng_header(100, 50, &[(0, 0, 100, 50), (10, 10, 91, 40)]);
+        assert!(matches!(
+            Probe::read(&apng, ImageFormat::Png, &InputLimits::default()),
+            Err(TransformError::DecodeError(DecodeError::InvalidFrame(1)))
+        ));
+
+        let mut encoder =
+            crate::transform::animation::libwebp::AnimationEncoder::new(300, 200, 50.0, 0).unwrap();
+        for alpha in 0..3 {
+            encoder.add(&vec![alpha; 300 * 200 * 4], 100).unwrap();
+        }
+        let webp = encoder.finish().unwrap();
+        assert_eq!(
+            Probe::read(&webp, ImageFormat::WebP, &InputLimits::default()).unwrap(),
+            Probe {
+                width: 300,
+                height: 200,
+                frames: 3
+            }
+        );
+    }
+}
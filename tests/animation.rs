@@ -0,0 +1,235 @@
+//! Runs the animations in `tests/animations` through the transformation to every target.
+
+use pxcmprs_server::transform::{
+    animation::{
+        libwebp::{AnimationDecoder, AnimationEncoder},
+        FrameSelection,
+    },
+    budget::Budget,
+    encoding::Encoding,
+    error::{DecodeError, TransformError},
+    limit::{DimensionLimits, InputLimits},
+    transform_vec, TransformResult,
+};
+use std::fs;
+use std::path::Path;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+/// The top left and bottom right pixel and the delay in milliseconds of a frame.
+type Frame = ([u8; 4], [u8; 4], u32);
+
+fn transform(bytes: Vec<u8>, target: Encoding) -> Vec<u8> {
+    transform_frame(bytes, None, target).unwrap()
+}
+
+fn transform_frame(
+    bytes: Vec<u8>,
+    frame: Option<FrameSelection>,
+    target: Encoding,
+) -> TransformResult<Vec<u8>> {
+    let limits = DimensionLimits {
+        jpeg: None,
+        webp: None,
+        png: None,
+        gif: None,
+        avif: None,
+        default: (1024, 1024),
+    };
+
+    transform_vec(
+        bytes,
+        (None, None),
+        frame,
+        &target,
+        &limits,
+        &InputLimits::default(),
+        &Budget::unlimited(),
+    )
+}
+
+/// Red for 100 ms, then a blue square blended over the bottom right corner for 250 ms, played
+/// twice.
+fn apng() -> Vec<u8> {
+    fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/animations/apng.png")).unwrap()
+}
+
+/// A still WebP in the extended format, which animations use as well: opaque red on the left
+/// and transparent on the right, 8×8 pixels.
+fn still_webp() -> Vec<u8> {
+    fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/animations/still.webp")).unwrap()
+}
+
+/// The frames of a GIF and how many times it's played, or `None` if it's played forever.
+fn gif_frames(bytes: &[u8]) -> (Vec<Frame>, Option<u16>) {
+    let mut decoder = gif::Decoder::new(bytes);
+    gif::SetParameter::set(&mut decoder, gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info().unwrap();
+
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        let pixel = |index: usize| {
+            let mut pixel = [0; 4];
+            pixel.copy_from_slice(&frame.buffer[index * 4..index * 4 + 4]);
+            pixel
+        };
+        let last = frame.width as usize * frame.height as usize - 1;
+        frames.push((pixel(0), pixel(last), frame.delay as u32 * 10));
+    }
+
+    let plays = match pxcmprs_server::transform::animation::repeat(bytes) {
+        None => Some(1),
+        Some(gif::Repeat::Infinite) => None,
+        Some(gif::Repeat::Finite(count)) => Some(count + 1),
+    };
+
+    (frames, plays)
+}
+
+/// The frames of an animated WebP and how many times it's played, or 0 if it's played forever.
+fn webp_frames(bytes: &[u8]) -> (Vec<Frame>, u32) {
+    let mut decoder = AnimationDecoder::new(bytes).unwrap();
+    let (width, height) = decoder.dimensions();
+
+    let mut frames = Vec::new();
+    while let Some((image, delay)) = decoder.next_frame().unwrap() {
+        frames.push((
+            image.get_pixel(0, 0).0,
+            image.get_pixel(width - 1, height - 1).0,
+            delay,
+        ));
+    }
+
+    (frames, decoder.plays())
+}
+
+#[test]
+fn test_apng_to_gif() {
+    let (frames, plays) = gif_frames(&transform(apng(), Encoding::Gif(100)));
+
+    assert_eq!(frames, vec![(RED, RED, 100), (RED, BLUE, 250)]);
+    assert_eq!(plays, Some(2));
+}
+
+#[test]
+fn test_apng_to_webp() {
+    let (frames, plays) = webp_frames(&transform(apng(), Encoding::WebP(100.0)));
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(
+        frames.iter().map(|frame| frame.2).collect::<Vec<_>>(),
+        vec![100, 250]
+    );
+    assert_eq!(plays, 2);
+}
+
+/// Alternating red and blue frames with `delays` in hundredths of a second, played forever.
+fn gif(delays: &[u16]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, 2, 2, &[255, 0, 0, 0, 0, 255]).unwrap();
+        gif::SetParameter::set(&mut encoder, gif::Repeat::Infinite).unwrap();
+        for (index, delay) in delays.iter().enumerate() {
+            let frame = gif::Frame {
+                width: 2,
+                height: 2,
+                delay: *delay,
+                buffer: vec![index as u8 % 2; 4].into(),
+                ..Default::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+    }
+    bytes
+}
+
+#[test]
+fn test_short_delays_to_webp() {
+    let delays = |bytes: &[u8]| {
+        let (frames, _) = webp_frames(bytes);
+        frames.iter().map(|frame| frame.2).collect::<Vec<_>>()
+    };
+
+    // Browsers show GIF frames of 10 ms or less for 100 ms.
+    let webp = transform(gif(&[0, 1, 2]), Encoding::WebP(100.0));
+    assert_eq!(delays(&webp), vec![100, 100, 20]);
+
+    // Other formats keep their delays.
+    let mut encoder = AnimationEncoder::new(2, 2, 100.0, 0).unwrap();
+    encoder.add(&[255; 16], 10).unwrap();
+    encoder.add(&[0; 16], 20).unwrap();
+    let webp = transform(encoder.finish().unwrap(), Encoding::WebP(100.0));
+    assert_eq!(delays(&webp), vec![10, 20]);
+}
+
+#[test]
+fn test_webp_to_gif() {
+    let webp = transform(apng(), Encoding::WebP(100.0));
+    let (frames, plays) = gif_frames(&transform(webp, Encoding::Gif(100)));
+
+    assert_eq!(
+        frames.iter().map(|frame| frame.2).collect::<Vec<_>>(),
+        vec![100, 250]
+    );
+    assert_eq!(plays, Some(2));
+}
+
+#[test]
+fn test_extended_still_webp() {
+    for target in [Encoding::Png, Encoding::Gif(100), Encoding::WebP(100.0)] {
+        let output = transform(still_webp(), target.clone());
+        // The transparency makes the WebP extended as well.
+        let image = match target {
+            Encoding::WebP(_) => webp::Decoder::new(&output).decode().unwrap().to_image(),
+            _ => image::load_from_memory(&output).unwrap(),
+        }
+        .to_rgba8();
+
+        assert_eq!(image.dimensions(), (8, 8), "{:?}", target);
+        assert_eq!(image.get_pixel(0, 0).0[3], 255, "{:?}", target);
+        assert_eq!(image.get_pixel(7, 7).0[3], 0, "{:?}", target);
+    }
+
+    // An animation of a single frame is a still as well.
+    let mut encoder = AnimationEncoder::new(2, 2, 100.0, 0).unwrap();
+    encoder.add(&[255; 16], 100).unwrap();
+    let png = transform(encoder.finish().unwrap(), Encoding::Png);
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (2, 2));
+}
+
+#[test]
+fn test_animation_to_still() {
+    let png = transform(apng(), Encoding::Png);
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+
+    // The first frame.
+    assert_eq!(image.get_pixel(3, 3).0, RED);
+}
+
+#[test]
+fn test_frame_selection() {
+    let pixel = |frame, target| {
+        let output = transform_frame(apng(), Some(frame), target).unwrap();
+        image::load_from_memory(&output)
+            .unwrap()
+            .to_rgba8()
+            .get_pixel(3, 3)
+            .0
+    };
+
+    assert_eq!(pixel(FrameSelection::Index(0), Encoding::Png), RED);
+    assert_eq!(pixel(FrameSelection::Last, Encoding::Png), BLUE);
+    assert_eq!(pixel(FrameSelection::Middle, Encoding::Png), BLUE);
+
+    // A selected frame is a still image, even in a format that can be animated.
+    let gif = transform_frame(apng(), Some(FrameSelection::Last), Encoding::Gif(100)).unwrap();
+    let (frames, _) = gif_frames(&gif);
+    assert_eq!(frames, vec![(RED, BLUE, 0)]);
+
+    match transform_frame(apng(), Some(FrameSelection::Index(2)), Encoding::Png) {
+        Err(TransformError::DecodeError(DecodeError::FrameOutOfRange(2, 2))) => {}
+        _ => panic!("frame 2 of 2 was not rejected"),
+    }
+}
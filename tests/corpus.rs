@@ -0,0 +1,91 @@
+//! Runs the corpus of malformed inputs in `tests/corpus` through the transformation. Every input
+//! must be rejected with an error, never panic or produce an output.
+
+use pxcmprs_server::transform::{
+    budget::Budget,
+    encoding::Encoding,
+    limit::{DimensionLimits, InputLimits},
+    transform_vec,
+};
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+fn targets() -> Vec<Encoding> {
+    vec![
+        Encoding::Gif(85),
+        Encoding::Png,
+        Encoding::Jpeg(80),
+        Encoding::WebP(80.0),
+        Encoding::Avif(50, 10),
+    ]
+}
+
+fn assert_rejected(directory: &str) {
+    let limits = DimensionLimits {
+        jpeg: None,
+        webp: None,
+        png: None,
+        gif: None,
+        avif: None,
+        default: (1024, 1024),
+    };
+
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/corpus")
+        .join(directory);
+    let mut inputs: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    for input in inputs {
+        let bytes = fs::read(&input).unwrap();
+
+        for target in targets() {
+            let result = panic::catch_unwind(|| {
+                transform_vec(
+                    bytes.clone(),
+                    (Some(100), None),
+                    None,
+                    &target,
+                    &limits,
+                    &InputLimits::default(),
+                    &Budget::unlimited(),
+                )
+            });
+
+            match result {
+                Err(_) => panic!("{} panicked for {:?}", input.display(), target),
+                Ok(Ok(_)) => panic!("{} was accepted for {:?}", input.display(), target),
+                Ok(Err(err)) => {
+                    let status = err.status_code();
+                    assert!(
+                        status.is_client_error() || status.is_server_error(),
+                        "{} failed with {} for {:?}",
+                        input.display(),
+                        status,
+                        target
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_malformed_gifs() {
+    assert_rejected("gif");
+}
+
+#[test]
+fn test_malformed_apngs() {
+    assert_rejected("apng");
+}
+
+#[test]
+fn test_malformed_webps() {
+    assert_rejected("webp");
+}